@@ -0,0 +1,93 @@
+use crate::val::Val;
+use std::{borrow::Cow, fmt};
+
+/// Category of an error. Scripts can read it from a caught error with `e.kind`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Error, //Anything without a more specific kind
+    Type,  //An operation applied to values of the wrong type
+    Name,  //An undeclared variable or function
+    Index, //A position outside of a vector or string
+    User,  //Raised by a script with throw
+    Assertion,
+    Import,       //A module that can't be found or imports itself
+    Overflow,     //Integer arithmetic whose result doesn't fit in 64 bits
+    ZeroDivision, //An int divided by zero
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Error => "Error",
+            ErrorKind::Type => "TypeError",
+            ErrorKind::Name => "NameError",
+            ErrorKind::Index => "IndexError",
+            ErrorKind::User => "UserError",
+            ErrorKind::Assertion => "AssertionError",
+            ErrorKind::Import => "ImportError",
+            ErrorKind::Overflow => "OverflowError",
+            ErrorKind::ZeroDivision => "ZeroDivisionError",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Error produced while tokenizing, parsing or running a script.
+/// Most messages are static, but some of them name the value that caused them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    kind: ErrorKind,
+    message: Cow<'static, str>,
+    value: Option<Box<Val>>, // Value given to throw, read by scripts with `e.value`
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: impl Into<Cow<'static, str>>) -> Error {
+        Error {
+            kind,
+            message: message.into(),
+            value: None,
+        }
+    }
+
+    /// Error raised by `throw value`. Its message is the value as a string.
+    pub fn thrown(value: Val) -> Error {
+        Error {
+            kind: ErrorKind::User,
+            message: value.to_string().into(),
+            value: Some(Box::new(value)),
+        }
+    }
+
+    pub fn value(&self) -> Option<&Val> {
+        self.value.as_deref()
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl From<&'static str> for Error {
+    fn from(message: &'static str) -> Self {
+        Error::new(ErrorKind::Error, message)
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::new(ErrorKind::Error, message)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {}
@@ -1,31 +1,419 @@
-use crate::{parser::*, val::Val};
-use std::collections::BTreeMap;
+use crate::{
+    error::{Error, ErrorKind},
+    methods::{find_method, Method},
+    parser::*,
+    val::{Map, Range, Record, Val},
+};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt, iter,
+    path::PathBuf,
+    rc::{Rc, Weak},
+};
 
-type Error = &'static str;
+const MAX_CALL_DEPTH: usize = 10_000;
+// A call in a debug build can take tens of kilobytes of stack. When less than
+// STACK_RED_ZONE is left, calls continue on a new STACK_SEGMENT allocated on the heap.
+const STACK_RED_ZONE: usize = 256 * 1024;
+const STACK_SEGMENT: usize = 4 * 1024 * 1024;
 
+/// Anything that can be called from an expression with `name(arguments)`.
+/// The interpreter implements it for the functions declared with `fn`, which are
+/// stored in variables like any other value.
+pub trait Function: fmt::Debug {
+    fn call(&self, env: &mut Environment, arguments: Vec<Val>) -> Result<Val, Error>;
+}
+
+/// Anonymous function created by a lambda literal such as `|x| x * 2`.
+/// Variables read by its body are copied when the lambda is created. A lambda
+/// assigned to a variable also sees itself under the name of that variable.
+#[derive(Debug)]
+struct Lambda {
+    name: Option<String>,
+    parameters: Vec<String>,
+    body: ParseExprNode,
+    captured: BTreeMap<String, Val>,
+    globals: GlobalScope,
+    this: Weak<Lambda>,
+}
+
+impl Function for Lambda {
+    fn call(&self, env: &mut Environment, arguments: Vec<Val>) -> Result<Val, Error> {
+        if arguments.len() != self.parameters.len() {
+            return Err("Wrong number of arguments".into());
+        }
+        let mut frame = self.captured.clone();
+        if let (Some(name), Some(this)) = (&self.name, self.this.upgrade()) {
+            frame.insert(name.clone(), Val::Function(this));
+        }
+        frame.extend(self.parameters.iter().cloned().zip(arguments));
+        env.call_in_frame(self.globals, frame, |env| env.evaluate(&self.body))
+    }
+}
+
+/// Modules loaded by import statements, so every module is only executed once
 #[derive(Debug, Default)]
-pub struct Environment {
+pub struct Modules {
+    pub search_path: Vec<PathBuf>, // Directories searched after the one of the importing script
+    pub loaded: HashMap<PathBuf, Val>,
+    pub loading: Vec<PathBuf>, // Modules being executed, used to detect circular imports
+}
+
+#[derive(Debug, Default)]
+struct Scope {
     variables: BTreeMap<String, Val>,
-    //functions : std::collections::HashMap<&str, _>
+    constants: BTreeSet<String>,
+}
+
+/// Global scope of the script or of one of the modules it imports
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlobalScope(usize);
+
+/// Function call, or module being executed
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    start: usize, // Index of its first scope
+    globals: GlobalScope,
+}
+
+/// Variables live in a chain of scopes. Every block pushes a scope on top and every
+/// function call starts a new frame, whose scopes can see their own variables and the
+/// globals of the script or module where the function was declared.
+#[derive(Debug)]
+pub struct Environment {
+    globals: Vec<Scope>, // The script comes first, then the modules in the order they were loaded
+    scopes: Vec<Scope>,
+    frames: Vec<Frame>,
+    modules: RefCell<Modules>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Applies `indexes` to `copy`, a value that isn't stored in a variable
+fn index_copy(mut copy: Val, indexes: impl IntoIterator<Item = Val>) -> Result<Val, Error> {
+    for i in indexes {
+        copy = match i {
+            Val::Range(r) => copy.slice(r)?,
+            Val::Str(member) if matches!(copy, Val::Module(_)) => copy.field(&member)?,
+            i => copy.index(i)?.clone(),
+        }
+    }
+    Ok(copy)
 }
 
 impl Environment {
     pub fn new() -> Environment {
         Environment {
-            variables: BTreeMap::new(),
+            globals: vec![Scope::default()],
+            scopes: vec![],
+            frames: vec![],
+            modules: RefCell::default(),
+        }
+    }
+
+    pub fn modules(&self) -> &RefCell<Modules> {
+        &self.modules
+    }
+
+    fn frame(&self) -> Frame {
+        self.frames.last().copied().unwrap_or(Frame {
+            start: 0,
+            globals: GlobalScope(0),
+        })
+    }
+
+    /// Global scope seen by the code being run, the one functions declared now will see
+    pub fn global_scope(&self) -> GlobalScope {
+        self.frame().globals
+    }
+
+    /// Innermost visible scope that binds `key`
+    fn find_scope(&mut self, key: &str) -> Option<&mut Scope> {
+        let frame = self.frame();
+        self.scopes[frame.start..]
+            .iter_mut()
+            .rev()
+            .chain(iter::once(&mut self.globals[frame.globals.0]))
+            .find(|scope| scope.variables.contains_key(key))
+    }
+
+    fn current_scope(&mut self) -> &mut Scope {
+        let frame = self.frame();
+        match self.scopes[frame.start..].last_mut() {
+            Some(scope) => scope,
+            None => &mut self.globals[frame.globals.0],
         }
     }
 
-    pub fn insert(&mut self, variable: String, value: Val) {
-        self.variables.insert(variable, value);
+    /// Assigns to the nearest binding of `variable`, or creates it in the current scope
+    pub fn insert(&mut self, variable: String, value: Val) -> Result<(), Error> {
+        let scope = match self.find_scope(&variable) {
+            Some(scope) => scope,
+            None => self.current_scope(),
+        };
+        if scope.constants.contains(&variable) {
+            return Err(format!("Cannot assign to constant {}", variable).into());
+        }
+        scope.variables.insert(variable, value);
+        Ok(())
+    }
+
+    /// Creates a binding in the current scope, shadowing any outer one.
+    /// Constants of the current scope can't be declared again.
+    pub fn declare(&mut self, variable: String, value: Val) -> Result<(), Error> {
+        let scope = self.current_scope();
+        if scope.constants.contains(&variable) {
+            return Err(format!("Cannot redeclare constant {}", variable).into());
+        }
+        scope.variables.insert(variable, value);
+        Ok(())
     }
 
+    /// Like `declare`, but the binding can't be assigned or modified afterwards
+    pub fn declare_constant(&mut self, constant: String, value: Val) -> Result<(), Error> {
+        self.declare(constant.clone(), value)?;
+        self.current_scope().constants.insert(constant);
+        Ok(())
+    }
+
+    fn find_variable(&mut self, key: &str) -> Result<&mut Val, Error> {
+        self.find_scope(key)
+            .and_then(|scope| scope.variables.get_mut(key))
+            .ok_or_else(|| Error::new(ErrorKind::Name, "Undeclared variable"))
+    }
+
+    /// Mutable reference to a variable, constants can't be modified through it
     pub fn get_mut_ref(&mut self, key: &str) -> Result<&mut Val, Error> {
-        self.variables.get_mut(key).ok_or("Undeclared variable")
+        match self.find_scope(key) {
+            Some(scope) if scope.constants.contains(key) => {
+                Err(format!("Cannot assign to constant {}", key).into())
+            }
+            _ => self.find_variable(key),
+        }
     }
 
     pub fn get_ref(&mut self, key: &str) -> Result<&Val, Error> {
-        self.variables.get(key).ok_or("Undeclared variable")
+        Ok(self.find_variable(key)?)
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(Scope::default());
+    }
+
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Runs `body`, the code of a function call, in a new frame whose first scope is
+    /// `frame`. The variables of `globals` are still visible from inside the frame.
+    pub fn call_in_frame<T>(
+        &mut self,
+        globals: GlobalScope,
+        frame: BTreeMap<String, Val>,
+        body: impl FnOnce(&mut Environment) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let first_scope = Scope {
+            variables: frame,
+            constants: BTreeSet::new(),
+        };
+        self.run_frame(globals, Some(first_scope), body)
+    }
+
+    /// Runs `body`, the code of a module, with a new global scope, and returns the
+    /// globals it declared. Functions declared by the module keep seeing that scope.
+    pub fn execute_module(
+        &mut self,
+        body: impl FnOnce(&mut Environment) -> Result<(), Error>,
+    ) -> Result<BTreeMap<String, Val>, Error> {
+        let globals = GlobalScope(self.globals.len());
+        self.globals.push(Scope::default());
+        self.run_frame(globals, None, body)?;
+        Ok(self.globals[globals.0].variables.clone())
+    }
+
+    /// The depth of the recursion is limited by MAX_CALL_DEPTH, not by the stack of the thread
+    fn run_frame<T>(
+        &mut self,
+        globals: GlobalScope,
+        first_scope: Option<Scope>,
+        body: impl FnOnce(&mut Environment) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err("Maximum recursion depth exceeded".into());
+        }
+        let start = self.scopes.len();
+        self.frames.push(Frame { start, globals });
+        self.scopes.extend(first_scope);
+        let result = stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, || body(self));
+        self.frames.pop();
+        self.scopes.truncate(start);
+        result
+    }
+
+    /// Calls the function stored in the variable `name`
+    pub fn call_function(&mut self, name: &str, arguments: Vec<Val>) -> Result<Val, Error> {
+        let function = match self.get_ref(name) {
+            Ok(Val::Function(f)) => f.clone(),
+            _ => return Err(Error::new(ErrorKind::Name, "Undeclared function")),
+        };
+        function.call(self, arguments)
+    }
+
+    fn evaluate_arguments(&mut self, arguments: &[ParseExprNode]) -> Result<Vec<Val>, Error> {
+        arguments.iter().map(|n| self.evaluate(n)).collect()
+    }
+
+    fn execute_fn_call(&mut self, name: &str, arguments: &[ParseExprNode]) -> Result<Val, Error> {
+        let computed_arguments = self.evaluate_arguments(arguments)?;
+        self.call_function(name, computed_arguments)
+    }
+
+    /// Variable and indexes of the receiver of a method, if it is an element that can be
+    /// modified in place, such as `v`, `v[1]` or `record.field`
+    fn receiver_path(
+        &mut self,
+        receiver: &ParseExprNode,
+    ) -> Result<Option<(String, Vec<Val>)>, Error> {
+        let path = match receiver {
+            ParseExprNode::VarName(name) => Some((name.clone(), vec![])),
+            ParseExprNode::VecAccess(name, index) => {
+                let index = self.evaluate_arguments(index)?;
+                let is_slice = index.iter().any(|i| matches!(i, Val::Range(_)));
+                Some((name.clone(), index)).filter(|_| !is_slice)
+            }
+            ParseExprNode::Field(value, field) => {
+                self.receiver_path(value)?.map(|(name, mut index)| {
+                    index.push(Val::Str(field.clone()));
+                    (name, index)
+                })
+            }
+            _ => None,
+        };
+        Ok(path)
+    }
+
+    /// Error for modifying `name[index]`, if it is read from a module, whose members can't
+    /// be modified
+    fn module_member_error(&mut self, name: &str, index: &[Val]) -> Option<Error> {
+        let mut element = self.find_variable(name).ok()?;
+        for i in index {
+            match element {
+                Val::Module(_) => return element.index(i.clone()).err(),
+                _ => element = element.index(i.clone()).ok()?,
+            }
+        }
+        None
+    }
+
+    fn element(&mut self, name: &str, index: Vec<Val>, mutable: bool) -> Result<&mut Val, Error> {
+        let mut element = match mutable {
+            true => self.get_mut_ref(name)?,
+            false => self.find_variable(name)?,
+        };
+        for i in index {
+            element = element.index(i)?;
+        }
+        Ok(element)
+    }
+
+    /// Calls `receiver.name(arguments)`. Functions stored in records and modules are
+    /// called as they are, otherwise `name` is a method of the type of the receiver.
+    /// Methods that modify the receiver modify the variable it is read from.
+    fn execute_method_call(
+        &mut self,
+        receiver: &ParseExprNode,
+        name: &str,
+        arguments: &[ParseExprNode],
+    ) -> Result<Val, Error> {
+        let path = self.receiver_path(receiver)?;
+        let module_member_error = path
+            .as_ref()
+            .and_then(|(variable, index)| self.module_member_error(variable, index));
+        // Fields of modules and errors are read, but can't be indexed
+        let path =
+            path.filter(|(variable, index)| self.element(variable, index.clone(), false).is_ok());
+        let arguments = self.evaluate_arguments(arguments)?;
+        let mut temporary = match path {
+            Some(_) => Val::Null,
+            None => self.evaluate(receiver)?,
+        };
+        let target = match &path {
+            Some((variable, index)) => self.element(variable, index.clone(), false)?,
+            None => &mut temporary,
+        };
+        if let Val::Record(_) | Val::Module(_) = target {
+            return match target.field(name)? {
+                Val::Function(f) => f.call(self, arguments),
+                _ => Err(Error::new(ErrorKind::Type, "Only functions can be called")),
+            };
+        }
+        match find_method(target, name)? {
+            Method::Read(method) => method(target, arguments),
+            Method::Write(method) => match (path, module_member_error) {
+                (Some((variable, index)), _) => {
+                    method(self.element(&variable, index, true)?, arguments)
+                }
+                (None, Some(error)) => Err(error),
+                (None, None) => method(&mut temporary, arguments),
+            },
+        }
+    }
+
+    fn execute_call(
+        &mut self,
+        callee: &ParseExprNode,
+        arguments: &[ParseExprNode],
+    ) -> Result<Val, Error> {
+        if let ParseExprNode::Field(receiver, name) = callee {
+            return self.execute_method_call(receiver, name, arguments);
+        }
+        match self.evaluate(callee)? {
+            Val::Function(f) => {
+                let computed_arguments = self.evaluate_arguments(arguments)?;
+                f.call(self, computed_arguments)
+            }
+            _ => Err(Error::new(ErrorKind::Type, "Only functions can be called")),
+        }
+    }
+
+    fn create_lambda(
+        &mut self,
+        name: Option<&str>,
+        parameters: &[String],
+        body: &ParseExprNode,
+    ) -> Val {
+        let mut captured = BTreeMap::new();
+        body.for_each_variable(&mut |name| {
+            if !parameters.iter().any(|p| p == name) {
+                if let Ok(value) = self.get_ref(name) {
+                    captured.insert(name.to_owned(), value.clone());
+                }
+            }
+        });
+        Val::Function(Rc::new_cyclic(|this| Lambda {
+            name: name.map(str::to_owned),
+            parameters: parameters.to_vec(),
+            body: body.clone(),
+            captured,
+            globals: self.global_scope(),
+            this: this.clone(),
+        }))
+    }
+
+    /// Evaluates the value assigned to the variable `name`, so that a lambda
+    /// assigned to it can call itself recursively
+    pub fn evaluate_binding(&mut self, name: &str, value: &ParseExprNode) -> Result<Val, Error> {
+        match value {
+            ParseExprNode::Lambda(parameters, body) => {
+                Ok(self.create_lambda(Some(name), parameters, body))
+            }
+            _ => self.evaluate(value),
+        }
     }
 
     fn execute_vec(&mut self, v: &[ParseExprNode]) -> Result<Val, Error> {
@@ -36,25 +424,111 @@ impl Environment {
         ))
     }
 
+    fn execute_record(&mut self, fields: &[(String, ParseExprNode)]) -> Result<Val, Error> {
+        let mut record = Record {
+            name: None,
+            fields: Vec::with_capacity(fields.len()),
+        };
+        for (name, value) in fields {
+            if record.get(name).is_some() {
+                return Err(format!("Duplicate field {} in record", name).into());
+            }
+            record.fields.push((name.clone(), self.evaluate(value)?));
+        }
+        Ok(Val::Record(record))
+    }
+
+    fn execute_map(&mut self, entries: &[(ParseExprNode, ParseExprNode)]) -> Result<Val, Error> {
+        let mut map = Map::default();
+        for (key, value) in entries {
+            let key = self.evaluate(key)?;
+            map.insert(key, self.evaluate(value)?)?;
+        }
+        Ok(Val::Map(map))
+    }
+
     fn execute_vec_access(&mut self, name: &str, index: &[ParseExprNode]) -> Result<Val, Error> {
         let computed_indexes: Result<Vec<_>, _> = index.iter().map(|n| self.evaluate(n)).collect();
-        let mut a = self.get_mut_ref(name)?;
-        for i in computed_indexes? {
-            a = a.index(i)?
+        let mut a = self.find_variable(name)?;
+        let mut indexes = computed_indexes?.into_iter();
+        while let Some(i) = indexes.next() {
+            // Slices and module members are copies, so the remaining indexes are applied to the copy
+            let copy = match i {
+                Val::Range(r) => a.slice(r)?,
+                Val::Str(member) if matches!(a, Val::Module(_)) => a.field(&member)?,
+                i => {
+                    a = a.index(i)?;
+                    continue;
+                }
+            };
+            return index_copy(copy, indexes);
         }
         Ok(a.clone())
     }
 
+    fn execute_index(
+        &mut self,
+        value: &ParseExprNode,
+        index: &[ParseExprNode],
+    ) -> Result<Val, Error> {
+        let value = self.evaluate(value)?;
+        let computed_indexes = self.evaluate_arguments(index)?;
+        index_copy(value, computed_indexes)
+    }
+
+    fn execute_range(
+        &mut self,
+        limits: &[ParseExprNode; 3],
+        inclusive: bool,
+    ) -> Result<Val, Error> {
+        let start = match self.evaluate(&limits[0])? {
+            Val::Null => Val::Int(0),
+            n => n,
+        };
+        let end = match self.evaluate(&limits[1])? {
+            Val::Null => None,
+            n => Some(n),
+        };
+        let step = self.evaluate(&limits[2])?;
+        Ok(Val::Range(Range::new(
+            &start,
+            end.as_ref(),
+            &step,
+            inclusive,
+        )?))
+    }
+
+    /// Evaluates only the value selected by the condition
+    fn execute_conditional(&mut self, parts: &[ParseExprNode; 3]) -> Result<Val, Error> {
+        match self.evaluate(&parts[0])? {
+            Val::Bool(true) => self.evaluate(&parts[1]),
+            Val::Bool(false) => self.evaluate(&parts[2]),
+            _ => Err(Error::new(
+                ErrorKind::Type,
+                "Conditional expressions only work with booleans",
+            )),
+        }
+    }
+
     pub fn evaluate(&mut self, node: &ParseExprNode) -> Result<Val, Error> {
         match node {
-            ParseExprNode::VarName(a) => Ok(self.get_mut_ref(a)?.clone()),
+            ParseExprNode::VarName(a) => self.get_ref(a).cloned(),
             ParseExprNode::Number(n) => Ok(n.clone()),
             ParseExprNode::String(s) => Ok(s.clone()),
             ParseExprNode::Bool(b) => Ok(b.clone()),
             ParseExprNode::Null => Ok(Val::Null),
+            ParseExprNode::FnCall(name, arguments) => self.execute_fn_call(name, arguments),
+            ParseExprNode::Call(callee, arguments) => self.execute_call(callee, arguments),
+            ParseExprNode::Lambda(parameters, body) => {
+                Ok(self.create_lambda(None, parameters, body))
+            }
             ParseExprNode::VecAccess(name, index) => self.execute_vec_access(name, index),
+            ParseExprNode::Index(value, index) => self.execute_index(value, index),
+            ParseExprNode::Field(value, name) => self.evaluate(value)?.field(name),
             ParseExprNode::Vector(v) => self.execute_vec(v),
-            ParseExprNode::Neg(n) => Ok(self.evaluate(&n)?.minus()?),
+            ParseExprNode::Record(fields) => self.execute_record(fields),
+            ParseExprNode::Map(entries) => self.execute_map(entries),
+            ParseExprNode::Neg(n) => Ok(self.evaluate(n)?.minus()?),
             ParseExprNode::Mul(s) => self.evaluate(&s[0])?.mul(self.evaluate(&s[1])?),
             ParseExprNode::Div(s) => self.evaluate(&s[0])?.div(self.evaluate(&s[1])?),
             ParseExprNode::Rem(s) => self.evaluate(&s[0])?.rem(self.evaluate(&s[1])?),
@@ -66,11 +540,25 @@ impl Environment {
             }
             ParseExprNode::And(s) => self.evaluate(&s[0])?.and(self.evaluate(&s[1])?),
             ParseExprNode::Or(s) => self.evaluate(&s[0])?.or(self.evaluate(&s[1])?),
-            ParseExprNode::Not(b) => Ok(self.evaluate(&b)?.not()?),
+            ParseExprNode::Not(b) => Ok(self.evaluate(b)?.not()?),
             ParseExprNode::Gt(s) => Ok(Val::Bool(self.evaluate(&s[0])? > self.evaluate(&s[1])?)),
             ParseExprNode::Lt(s) => Ok(Val::Bool(self.evaluate(&s[0])? < self.evaluate(&s[1])?)),
             ParseExprNode::Gtoe(s) => Ok(Val::Bool(self.evaluate(&s[0])? >= self.evaluate(&s[1])?)),
+            ParseExprNode::Range(r) => self.execute_range(r, false),
+            ParseExprNode::RangeInclusive(r) => self.execute_range(r, true),
+            ParseExprNode::In(s) => {
+                let element = self.evaluate(&s[0])?;
+                Ok(Val::Bool(self.evaluate(&s[1])?.contains(&element)?))
+            }
             ParseExprNode::Ltoe(s) => Ok(Val::Bool(self.evaluate(&s[0])? <= self.evaluate(&s[1])?)),
+            ParseExprNode::Conditional(parts) => self.execute_conditional(parts),
+            ParseExprNode::Interpolation(parts) => {
+                let mut text = String::new();
+                for part in parts {
+                    text += &self.evaluate(part)?.to_string();
+                }
+                Ok(Val::Str(text))
+            }
         }
     }
 }
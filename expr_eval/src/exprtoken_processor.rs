@@ -1,16 +1,25 @@
-use crate::tokenizer::ExprToken;
-type Error = &'static str;
+use crate::{
+    error::Error,
+    tokenizer::{interpolation_len, tokenize_expr, ExprToken},
+};
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum ProcessedExprToken {
     VarName(String),
+    Int(i64),
     Number(f64),
     String(String),
+    Interpolation(Vec<Vec<ProcessedExprToken>>), // Literal parts and embedded expressions
     Bool(bool),
     Null,
-    //FnCall(&'a str, Vec<ProcessedToken<'a>>),
-    //Dot
+    FnCall(String, Vec<ProcessedExprToken>),
+    Call(Box<ProcessedExprToken>, Vec<ProcessedExprToken>),
+    Lambda(Vec<String>, Vec<ProcessedExprToken>),
+    Field(Box<ProcessedExprToken>, String),
+    Record(Vec<(String, Vec<ProcessedExprToken>)>),
+    Map(Vec<(Vec<ProcessedExprToken>, Vec<ProcessedExprToken>)>),
     VecAccess(String, Vec<Vec<ProcessedExprToken>>),
+    Index(Box<ProcessedExprToken>, Vec<Vec<ProcessedExprToken>>),
     Vector(Vec<ProcessedExprToken>),
     OpenSBrackets,
     CloseSBrackets,
@@ -33,6 +42,12 @@ pub enum ProcessedExprToken {
     Or,
     Not(Option<Box<ProcessedExprToken>>),
     Comma,
+    Colon,
+    Question,
+    Range,
+    RangeInclusive,
+    Step,
+    In,
     Neg(Box<ProcessedExprToken>),
 }
 
@@ -47,29 +62,34 @@ fn find_matching_parentheses(i: usize, tokens: &[ExprToken]) -> Result<usize, Er
                     return Ok(index);
                 }
             }
-            ExprToken::OpenParentheses => nested_parentheses -= 1,
+            ExprToken::OpenParentheses | ExprToken::FnCallStart(_) => nested_parentheses -= 1,
             _ => {}
         }
     }
-    Err("Unable to find matching parentheses")
+    Err("Unable to find matching parentheses".into())
 }
 
 fn find_matching_square_bracket(i: usize, tokens: &[ExprToken]) -> Result<usize, Error> {
     let mut nested_bracket = -1;
     for (index, token) in tokens.iter().enumerate().skip(i + 1) {
         match token {
-            ExprToken::CloseSBrackets | ExprToken::VecAccessStart(_) => {
+            ExprToken::CloseSBrackets => {
                 nested_bracket += 1;
                 if nested_bracket == 0 {
                     return Ok(index);
                 }
             }
-            ExprToken::OpenSBrackets => nested_bracket -= 1,
+            // `][` closes an index and opens the next one of the same vector access
+            ExprToken::VecAccessStart(name) if name == "][" && nested_bracket == -1 => {
+                return Ok(index)
+            }
+            ExprToken::VecAccessStart(name) if name == "][" => {}
+            ExprToken::OpenSBrackets | ExprToken::VecAccessStart(_) => nested_bracket -= 1,
             _ => {}
         }
     }
 
-    Err("Unable to find matching square bracket")
+    Err("Unable to find matching square bracket".into())
 }
 
 fn process_vector(tokens: &[ExprToken], i: &mut usize) -> Result<ProcessedExprToken, Error> {
@@ -81,13 +101,12 @@ fn process_vector(tokens: &[ExprToken], i: &mut usize) -> Result<ProcessedExprTo
     )?))
 }
 
-fn process_vector_access(
+/// Processes the indexes of the vector access that starts at `tokens[*i]`, such as `a[1]` or `a[1][2]`
+fn process_indexes(
     tokens: &[ExprToken],
     i: &mut usize,
-    capture: &str,
-) -> Result<ProcessedExprToken, Error> {
+) -> Result<Vec<Vec<ProcessedExprToken>>, Error> {
     let bracket_end = find_matching_square_bracket(*i, tokens)?;
-    let name = capture.trim_end_matches('[').to_string();
     match tokens[bracket_end] {
         ExprToken::VecAccessStart(_) => {
             let mut v = Vec::with_capacity(5);
@@ -97,19 +116,43 @@ fn process_vector_access(
                 let brackets_content = &tokens[*i + 1..a];
                 v.push(process_expr_tokens(brackets_content)?);
                 *i = a;
-                a < tokens.len() - 1
+                tokens[a] != ExprToken::CloseSBrackets
             } {}
-            Ok(ProcessedExprToken::VecAccess(name, v))
+            Ok(v)
         }
         ExprToken::CloseSBrackets => {
             let brackets_content = &tokens[*i + 1..bracket_end];
             *i = bracket_end;
-            Ok(ProcessedExprToken::VecAccess(
-                name,
-                vec![process_expr_tokens(brackets_content)?],
-            ))
+            Ok(vec![process_expr_tokens(brackets_content)?])
+        }
+        _ => Err("Erro preprocessing vector access".into()),
+    }
+}
+
+fn process_vector_access(
+    tokens: &[ExprToken],
+    i: &mut usize,
+    capture: &str,
+) -> Result<ProcessedExprToken, Error> {
+    let name = capture.trim_end_matches('[').to_string();
+    Ok(ProcessedExprToken::VecAccess(
+        name,
+        process_indexes(tokens, i)?,
+    ))
+}
+
+/// Variable and indexes of an operand that can be indexed in place: a variable, a
+/// vector access or a field of one of them. Fields become string indexes.
+fn access_path(value: &ProcessedExprToken) -> Option<(String, Vec<Vec<ProcessedExprToken>>)> {
+    match value {
+        ProcessedExprToken::VarName(name) => Some((name.clone(), vec![])),
+        ProcessedExprToken::VecAccess(name, path) => Some((name.clone(), path.clone())),
+        ProcessedExprToken::Field(value, field) => {
+            let (name, mut path) = access_path(value)?;
+            path.push(vec![ProcessedExprToken::String(field.clone())]);
+            Some((name, path))
         }
-        _ => Err("Erro preprocessing vector access"),
+        _ => None,
     }
 }
 
@@ -122,6 +165,285 @@ fn process_parentheses(tokens: &[ExprToken], i: &mut usize) -> Result<ProcessedE
     )?))
 }
 
+/// Processes the arguments of the call that starts at `tokens[*i]`, leaving `i` on its closing parentheses
+fn process_arguments(
+    tokens: &[ExprToken],
+    i: &mut usize,
+) -> Result<Vec<ProcessedExprToken>, Error> {
+    let parentheses_end = find_matching_parentheses(*i, tokens)?;
+    let arguments = &tokens[*i + 1..parentheses_end];
+    *i = parentheses_end;
+    process_expr_tokens(arguments)
+}
+
+fn process_fn_call(
+    tokens: &[ExprToken],
+    i: &mut usize,
+    name: &str,
+) -> Result<ProcessedExprToken, Error> {
+    Ok(ProcessedExprToken::FnCall(
+        name.to_owned(),
+        process_arguments(tokens, i)?,
+    ))
+}
+
+/// Finds where the element that starts at `i` ends: the next comma that is not nested
+/// inside parentheses or brackets, or the end of the expression. Used for the body of
+/// a lambda and the fields of a record.
+fn find_element_end(i: usize, tokens: &[ExprToken]) -> usize {
+    let mut nesting = 0;
+    for (index, token) in tokens.iter().enumerate().skip(i) {
+        match token {
+            ExprToken::OpenParentheses
+            | ExprToken::FnCallStart(_)
+            | ExprToken::OpenSBrackets
+            | ExprToken::OpenCBrackets => nesting += 1,
+            ExprToken::VecAccessStart(name) if name != "][" => nesting += 1,
+            ExprToken::CloseParentheses | ExprToken::CloseSBrackets | ExprToken::CloseCBrackets => {
+                nesting -= 1
+            }
+            ExprToken::Comma if nesting == 0 => return index,
+            _ => {}
+        }
+    }
+    tokens.len()
+}
+
+fn find_matching_curly_bracket(i: usize, tokens: &[ExprToken]) -> Result<usize, Error> {
+    let mut nested_brackets = 0;
+    for (index, token) in tokens.iter().enumerate().skip(i) {
+        match token {
+            ExprToken::OpenCBrackets => nested_brackets += 1,
+            ExprToken::CloseCBrackets => {
+                nested_brackets -= 1;
+                if nested_brackets == 0 {
+                    return Ok(index);
+                }
+            }
+            _ => {}
+        }
+    }
+    Err("Unable to find matching curly bracket".into())
+}
+
+/// Processes a record literal such as `{x: 1, y: 2}` or a map literal such as
+/// `{"x": 1, 2: true}`, leaving `i` on the closing bracket. `{}` is an empty map.
+fn process_record_or_map(tokens: &[ExprToken], i: &mut usize) -> Result<ProcessedExprToken, Error> {
+    let literal_end = find_matching_curly_bracket(*i, tokens)?;
+    let content = &tokens[*i + 1..literal_end];
+    *i = literal_end;
+    let mut entries = vec![];
+    let mut start = 0;
+    while start < content.len() {
+        let end = find_element_end(start, content);
+        let entry = &content[start..end];
+        match entry.iter().position(|t| *t == ExprToken::Colon) {
+            Some(colon) if colon > 0 && colon + 1 < entry.len() => {
+                entries.push((&entry[..colon], &entry[colon + 1..]))
+            }
+            _ => return Err("Expected key: value".into()),
+        }
+        start = end + 1;
+    }
+    let field_names = entries
+        .iter()
+        .filter_map(|(key, _)| match key {
+            [ExprToken::VarName(name)] => Some(name.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if field_names.is_empty() {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| Ok((process_expr_tokens(key)?, process_expr_tokens(value)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(ProcessedExprToken::Map(entries))
+    } else if field_names.len() == entries.len() {
+        let fields = field_names
+            .into_iter()
+            .zip(entries)
+            .map(|(name, (_, value))| Ok((name, process_expr_tokens(value)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(ProcessedExprToken::Record(fields))
+    } else {
+        Err("Record fields and map keys can't be mixed".into())
+    }
+}
+
+fn process_lambda(
+    tokens: &[ExprToken],
+    i: &mut usize,
+    has_parameters: bool,
+) -> Result<ProcessedExprToken, Error> {
+    let mut parameters = vec![];
+    if has_parameters {
+        loop {
+            *i += 1;
+            match tokens.get(*i) {
+                Some(ExprToken::VarName(name)) => parameters.push(name.clone()),
+                Some(ExprToken::Comma) => {}
+                Some(ExprToken::Pipe) => break,
+                _ => return Err("Invalid lambda parameters".into()),
+            }
+        }
+    }
+    let body_end = find_element_end(*i + 1, tokens);
+    let body = &tokens[*i + 1..body_end];
+    if body.is_empty() {
+        return Err("Lambda without body".into());
+    }
+    *i = body_end - 1;
+    Ok(ProcessedExprToken::Lambda(
+        parameters,
+        process_expr_tokens(body)?,
+    ))
+}
+
+/// Decodes the escape sequence at `start` in `literal`, which begins with a backslash.
+/// Returns the character it stands for and the length of the sequence.
+fn escape(literal: &str, start: usize) -> Result<(char, usize), Error> {
+    let sequence = &literal[start + 1..];
+    let escaped = match sequence.chars().next() {
+        Some('n') => Some(('\n', 2)),
+        Some('t') => Some(('\t', 2)),
+        Some('r') => Some(('\r', 2)),
+        Some('0') => Some(('\0', 2)),
+        Some(c @ ('\\' | '"' | '$')) => Some((c, 2)),
+        Some('x') => sequence
+            .get(1..3)
+            .filter(|hex| hex.chars().all(|c| c.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .filter(|byte| byte.is_ascii())
+            .map(|byte| (byte as char, 4)),
+        Some('u') => sequence
+            .strip_prefix("u{")
+            .and_then(|hex| hex.split_once('}'))
+            .map(|(hex, _)| hex)
+            .filter(|hex| {
+                (1..=6).contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit())
+            })
+            .and_then(|hex| {
+                Some((
+                    char::from_u32(u32::from_str_radix(hex, 16).ok()?)?,
+                    hex.len() + 4,
+                ))
+            }),
+        _ => None,
+    };
+    escaped.ok_or_else(|| {
+        let sequence: String = literal[start..].chars().take(2).collect();
+        format!(
+            "Invalid escape sequence {} at character {} of string {}",
+            sequence,
+            literal[..start].chars().count() + 1,
+            literal
+        )
+        .into()
+    })
+}
+
+/// Removes the line breaks after the opening and before the closing quotes of a multi-line
+/// string, and the indentation shared by all of its lines that aren't blank
+fn trim_indent(content: &str) -> String {
+    let mut lines: Vec<_> = content.lines().collect();
+    if lines.first().is_some_and(|l| l.trim().is_empty()) {
+        lines.remove(0);
+    }
+    if lines.last().is_some_and(|l| l.trim().is_empty()) {
+        lines.pop();
+    }
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start_matches(&[' ', '\t'][..]).len())
+        .min()
+        .unwrap_or_default();
+    lines
+        .iter()
+        .map(|l| l.get(indent..).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Converts a string literal into its text. Raw strings are taken as they are, other strings
+/// are split into their text and the expressions between `${` and `}`.
+fn process_string(literal: &str) -> Result<ProcessedExprToken, Error> {
+    if let Some(raw) = literal.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let content = &raw[hashes + 1..raw.len() - hashes - 1];
+        return Ok(ProcessedExprToken::String(content.to_owned()));
+    }
+    let quotes = r#"""""#;
+    if literal.starts_with(quotes) {
+        let content = trim_indent(&literal[quotes.len()..literal.len() - quotes.len()]);
+        process_text(&format!("{}{}{}", quotes, content, quotes), quotes.len())
+    } else {
+        process_text(literal, 1)
+    }
+}
+
+/// Splits the text between the quotes of a string literal into text and interpolated expressions.
+/// `\$` writes a dollar sign without starting an expression.
+fn process_text(literal: &str, quotes: usize) -> Result<ProcessedExprToken, Error> {
+    let end = literal.len() - quotes;
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut i = quotes;
+    while let Some(c) = literal[i..end].chars().next() {
+        let rest = &literal[i..end];
+        if c == '\\' {
+            let (escaped, len) = escape(literal, i)?;
+            text.push(escaped);
+            i += len;
+        } else if let Some(interpolation) = rest.strip_prefix("${") {
+            let len = interpolation_len(interpolation).ok_or("Unterminated interpolation")?;
+            let expr = &interpolation[..len - 1];
+            if expr.trim().is_empty() {
+                return Err("Empty interpolation".into());
+            }
+            if !text.is_empty() {
+                parts.push(vec![ProcessedExprToken::String(std::mem::take(&mut text))]);
+            }
+            parts.push(process_expr_tokens(&tokenize_expr(expr)?)?);
+            i += len + 2;
+        } else {
+            text.push(c);
+            i += c.len_utf8();
+        }
+    }
+    if parts.is_empty() {
+        return Ok(ProcessedExprToken::String(text));
+    }
+    if !text.is_empty() {
+        parts.push(vec![ProcessedExprToken::String(text)]);
+    }
+    Ok(ProcessedExprToken::Interpolation(parts))
+}
+
+fn is_operand(token: &ProcessedExprToken) -> bool {
+    matches!(
+        token,
+        ProcessedExprToken::Int(_)
+            | ProcessedExprToken::Number(_)
+            | ProcessedExprToken::String(_)
+            | ProcessedExprToken::Interpolation(_)
+            | ProcessedExprToken::Bool(_)
+            | ProcessedExprToken::Null
+            | ProcessedExprToken::CloseParentheses
+            | ProcessedExprToken::Parentheses(_)
+            | ProcessedExprToken::Neg(_)
+            | ProcessedExprToken::VarName(_)
+            | ProcessedExprToken::Vector(_)
+            | ProcessedExprToken::VecAccess(_, _)
+            | ProcessedExprToken::Index(_, _)
+            | ProcessedExprToken::FnCall(_, _)
+            | ProcessedExprToken::Call(_, _)
+            | ProcessedExprToken::Field(_, _)
+            | ProcessedExprToken::Record(_)
+            | ProcessedExprToken::Map(_)
+    )
+}
+
 fn process_not_and_negatives(tokens: &[ProcessedExprToken]) -> Vec<ProcessedExprToken> {
     let mut processed_tokens = Vec::with_capacity(tokens.len());
     let mut i = 0;
@@ -131,20 +453,11 @@ fn process_not_and_negatives(tokens: &[ProcessedExprToken]) -> Vec<ProcessedExpr
                 if i == 0 {
                     processed_tokens.push(ProcessedExprToken::Neg(Box::new(tokens[i + 1].clone())));
                     i += 1;
+                } else if is_operand(&tokens[i - 1]) {
+                    processed_tokens.push(ProcessedExprToken::Sub)
                 } else {
-                    match tokens[i - 1] {
-                        ProcessedExprToken::Number(_)
-                        | ProcessedExprToken::CloseParentheses
-                        | ProcessedExprToken::Neg(_)
-                        | ProcessedExprToken::VarName(_) => {
-                            processed_tokens.push(ProcessedExprToken::Sub)
-                        }
-                        _ => {
-                            processed_tokens
-                                .push(ProcessedExprToken::Neg(Box::new(tokens[i + 1].clone())));
-                            i += 1;
-                        }
-                    }
+                    processed_tokens.push(ProcessedExprToken::Neg(Box::new(tokens[i + 1].clone())));
+                    i += 1;
                 }
             }
             ProcessedExprToken::Not(_) => {
@@ -166,15 +479,68 @@ pub fn process_expr_tokens(tokens: &[ExprToken]) -> Result<Vec<ProcessedExprToke
     while index < tokens.len() {
         match &tokens[index] {
             ExprToken::VarName(a) => processed_tokens.push(ProcessedExprToken::VarName(a.clone())),
+            ExprToken::Int(a) => processed_tokens.push(ProcessedExprToken::Int(*a)),
             ExprToken::Number(a) => processed_tokens.push(ProcessedExprToken::Number(*a)),
-            ExprToken::String(a) => processed_tokens.push(ProcessedExprToken::String(
-                a.trim_matches('"').replace("\\n", "\n"),
-            )),
+            ExprToken::String(a) => processed_tokens.push(process_string(a)?),
             ExprToken::Bool(a) => processed_tokens.push(ProcessedExprToken::Bool(*a)),
             ExprToken::OpenParentheses => {
-                processed_tokens.push(process_parentheses(tokens, &mut index)?)
+                let parentheses = process_parentheses(tokens, &mut index)?;
+                // A parenthesized list right after a call, a vector access or another
+                // parenthesized expression calls its result
+                match (processed_tokens.pop(), parentheses) {
+                    (
+                        Some(
+                            callee @ (ProcessedExprToken::VecAccess(_, _)
+                            | ProcessedExprToken::FnCall(_, _)
+                            | ProcessedExprToken::Call(_, _)
+                            | ProcessedExprToken::Field(_, _)
+                            | ProcessedExprToken::Parentheses(_)),
+                        ),
+                        ProcessedExprToken::Parentheses(arguments),
+                    ) => {
+                        processed_tokens.push(ProcessedExprToken::Call(Box::new(callee), arguments))
+                    }
+                    (previous, parentheses) => {
+                        processed_tokens.extend(previous);
+                        processed_tokens.push(parentheses);
+                    }
+                }
             }
-            ExprToken::CloseParentheses => return Err("Unmatched )"),
+            ExprToken::CloseParentheses => return Err("Unmatched )".into()),
+            // `value.name` reads the field `name` of the operand before the dot,
+            // `value.name(arguments)` calls it
+            ExprToken::Dot => match (processed_tokens.pop(), tokens.get(index + 1)) {
+                (Some(value), Some(ExprToken::VarName(name))) if is_operand(&value) => {
+                    processed_tokens.push(ProcessedExprToken::Field(Box::new(value), name.clone()));
+                    index += 1;
+                }
+                (Some(value), Some(ExprToken::FnCallStart(name))) if is_operand(&value) => {
+                    index += 1;
+                    let arguments = process_arguments(tokens, &mut index)?;
+                    let field = ProcessedExprToken::Field(Box::new(value), name.clone());
+                    processed_tokens.push(ProcessedExprToken::Call(Box::new(field), arguments));
+                }
+                // `value.name[index]` indexes the field in place if the value is read from a
+                // variable, so it can also be written. Other values, such as the result of a
+                // call, are indexed after reading the field.
+                (Some(value), Some(ExprToken::VecAccessStart(name))) if is_operand(&value) => {
+                    index += 1;
+                    let field = name.trim_end_matches('[').to_owned();
+                    let indexes = process_indexes(tokens, &mut index)?;
+                    processed_tokens.push(match access_path(&value) {
+                        Some((variable, mut path)) => {
+                            path.push(vec![ProcessedExprToken::String(field)]);
+                            path.extend(indexes);
+                            ProcessedExprToken::VecAccess(variable, path)
+                        }
+                        None => {
+                            let field = ProcessedExprToken::Field(Box::new(value), field);
+                            ProcessedExprToken::Index(Box::new(field), indexes)
+                        }
+                    });
+                }
+                _ => return Err("Expected field name after dot".into()),
+            },
             ExprToken::Div => processed_tokens.push(ProcessedExprToken::Div),
             ExprToken::Mul => processed_tokens.push(ProcessedExprToken::Mul),
             ExprToken::Rem => processed_tokens.push(ProcessedExprToken::Rem),
@@ -187,14 +553,31 @@ pub fn process_expr_tokens(tokens: &[ExprToken]) -> Result<Vec<ProcessedExprToke
             ExprToken::Gtoe => processed_tokens.push(ProcessedExprToken::Gtoe),
             ExprToken::Ltoe => processed_tokens.push(ProcessedExprToken::Ltoe),
             ExprToken::And => processed_tokens.push(ProcessedExprToken::And),
+            ExprToken::Or if !processed_tokens.last().is_some_and(is_operand) => {
+                processed_tokens.push(process_lambda(tokens, &mut index, false)?)
+            }
             ExprToken::Or => processed_tokens.push(ProcessedExprToken::Or),
+            ExprToken::Pipe => processed_tokens.push(process_lambda(tokens, &mut index, true)?),
             ExprToken::Not => processed_tokens.push(ProcessedExprToken::Not(None)),
+            ExprToken::FnCallStart(name) => {
+                processed_tokens.push(process_fn_call(tokens, &mut index, name)?)
+            }
             ExprToken::VecAccessStart(name) => {
                 processed_tokens.push(process_vector_access(tokens, &mut index, name)?)
             }
             ExprToken::OpenSBrackets => processed_tokens.push(process_vector(tokens, &mut index)?),
-            ExprToken::CloseSBrackets => return Err("Unmatched ]"),
+            ExprToken::CloseSBrackets => return Err("Unmatched ]".into()),
+            ExprToken::OpenCBrackets => {
+                processed_tokens.push(process_record_or_map(tokens, &mut index)?)
+            }
+            ExprToken::CloseCBrackets => return Err("Unmatched }".into()),
+            ExprToken::Colon => processed_tokens.push(ProcessedExprToken::Colon),
+            ExprToken::Question => processed_tokens.push(ProcessedExprToken::Question),
             ExprToken::Comma => processed_tokens.push(ProcessedExprToken::Comma),
+            ExprToken::Range => processed_tokens.push(ProcessedExprToken::Range),
+            ExprToken::RangeInclusive => processed_tokens.push(ProcessedExprToken::RangeInclusive),
+            ExprToken::Step => processed_tokens.push(ProcessedExprToken::Step),
+            ExprToken::In => processed_tokens.push(ProcessedExprToken::In),
 
             ExprToken::Null => processed_tokens.push(ProcessedExprToken::Null),
         }
@@ -1,14 +1,22 @@
 #[macro_use]
 extern crate lazy_static;
+pub mod error;
 pub mod evaluator;
 pub mod exprtoken_processor;
+pub mod methods;
 pub mod parser;
 pub mod tokenizer;
 pub mod val;
 
 #[cfg(test)]
 mod tests {
-    use crate::{evaluator::Environment, parser::parse_expr, tokenizer::tokenize_expr, val::Val};
+    use crate::{
+        error::{Error, ErrorKind},
+        evaluator::Environment,
+        parser::parse_expr,
+        tokenizer::tokenize_expr,
+        val::Val,
+    };
 
     #[test]
     fn four_divided_by_2_plus_2() {
@@ -41,6 +49,7 @@ mod tests {
         assert_eq!(Val::Bool(4 == 2), result);
     }
     #[test]
+    #[allow(clippy::nonminimal_bool)]
     fn four_plus_1_gtoe_5_and_2_lt_3() {
         let tokens = tokenize_expr("4+1 >= 5 &&  2<3").unwrap();
         let ast = parse_expr(&tokens).unwrap();
@@ -92,6 +101,7 @@ mod tests {
 
     #[test]
     #[allow(unused_parens)]
+    #[allow(clippy::double_parens)]
     fn lot_of_parentheses() {
         let tokens = tokenize_expr("((1+2)*3/(5*(3+1)))").unwrap();
         let ast = parse_expr(&tokens).unwrap();
@@ -112,6 +122,12 @@ mod tests {
         assert_eq!(Val::Number(0.0 * 1.0 + 2.0 * 3.0 * 4.0 + 5.0 + 6.0), result);
     }
 
+    #[test]
+    fn missing_operand() {
+        let tokens = tokenize_expr("2 * * 3").unwrap();
+        assert_eq!(parse_expr(&tokens), Err("Error parsing multiplication".into()));
+    }
+
     #[test]
     fn three_plus_4_divided_by_5() {
         let tokens = tokenize_expr("3+4/5").unwrap();
@@ -131,4 +147,115 @@ mod tests {
         let result = env.evaluate(&ast).unwrap();
         assert_eq!(Val::Number((3.0 + 4.0) / 5.0), result);
     }
+
+    #[test]
+    fn lambda_call() {
+        let tokens = tokenize_expr("(|x, y| x * y + 1)(2, 3)").unwrap();
+        let ast = parse_expr(&tokens).unwrap();
+        let mut env = Environment::new();
+        let result = env.evaluate(&ast).unwrap();
+        assert_eq!(Val::Int(7), result);
+    }
+
+    #[test]
+    fn range_membership() {
+        let tokens = tokenize_expr("4 in 0..10 step 2 && !(5 in 0..10 step 2) && 5 in 1..=5").unwrap();
+        let ast = parse_expr(&tokens).unwrap();
+        let mut env = Environment::new();
+        let result = env.evaluate(&ast).unwrap();
+        assert_eq!(Val::Bool(true), result);
+    }
+
+    #[test]
+    fn record_literal() {
+        let tokens = tokenize_expr("{a: 1, b: {c: \"x\"}}.b.c + {a: 2}.a").unwrap();
+        let ast = parse_expr(&tokens).unwrap();
+        let mut env = Environment::new();
+        let result = env.evaluate(&ast).unwrap();
+        assert_eq!(Val::Str("x2".to_owned()), result);
+    }
+
+    #[test]
+    fn nested_conditional() {
+        let tokens = tokenize_expr("1 > 2 ? \"a\" : 2 > 1 ? \"b\" : \"c\"").unwrap();
+        let ast = parse_expr(&tokens).unwrap();
+        let mut env = Environment::new();
+        let result = env.evaluate(&ast).unwrap();
+        assert_eq!(Val::Str("b".to_owned()), result);
+    }
+
+    #[test]
+    fn string_interpolation() {
+        let tokens = tokenize_expr(r#""${1 + 2} is ${{a: "three"}.a}, \${x}""#).unwrap();
+        let ast = parse_expr(&tokens).unwrap();
+        let mut env = Environment::new();
+        let result = env.evaluate(&ast).unwrap();
+        assert_eq!(Val::Str("3 is three, ${x}".to_owned()), result);
+    }
+
+    #[test]
+    fn escape_sequences() {
+        let tokens = tokenize_expr(r#""\"a\"\t\\\r\0\x41\u{e9}\n""#).unwrap();
+        let ast = parse_expr(&tokens).unwrap();
+        let mut env = Environment::new();
+        let result = env.evaluate(&ast).unwrap();
+        assert_eq!(Val::Str("\"a\"\t\\\r\0A\u{e9}\n".to_owned()), result);
+        let tokens = tokenize_expr(r#""ok\x80""#).unwrap();
+        assert_eq!(
+            parse_expr(&tokens),
+            Err(r#"Invalid escape sequence \x at character 4 of string "ok\x80""#.into())
+        );
+    }
+
+    #[test]
+    fn raw_strings() {
+        let tokens = tokenize_expr(r###"r"\d+" + r#"a "b" ${c}"#"###).unwrap();
+        let ast = parse_expr(&tokens).unwrap();
+        let mut env = Environment::new();
+        let result = env.evaluate(&ast).unwrap();
+        assert_eq!(Val::Str(r#"\d+a "b" ${c}"#.to_owned()), result);
+    }
+
+    #[test]
+    fn ints_and_floats() {
+        let mut env = Environment::new();
+        let mut evaluate = |expr: &str| env.evaluate(&parse_expr(&tokenize_expr(expr)?)?);
+        assert_eq!(evaluate("7 / 2"), Ok(Val::Number(3.5)));
+        assert_eq!(evaluate("(7).div(2)"), Ok(Val::Int(3)));
+        assert_eq!(evaluate("(9007199254740993).to_int()"), Ok(Val::Int(9007199254740993)));
+        assert_eq!(evaluate("(9223372036854775807).to_int()"), Ok(Val::Int(i64::MAX)));
+        assert_eq!(evaluate("(-2.5).to_int()"), Ok(Val::Int(-2)));
+        assert_eq!(evaluate("\" 42\".to_int()"), Ok(Val::Int(42)));
+        assert_eq!(
+            evaluate("\"4.2\".to_int()"),
+            Err(Error::new(ErrorKind::Type, "\"4.2\" can't be converted to an int"))
+        );
+        assert_eq!(evaluate("(7.5).div(2)"), Ok(Val::Number(3.0)));
+        assert_eq!(evaluate("4 / 2").map(|v| v.to_string()), Ok("2.0".to_owned()));
+        assert_eq!(
+            evaluate("[9007199254740993 + 0, 3 * 1.0]").map(|v| v.to_string()),
+            Ok("[9007199254740993, 3.0]".to_owned())
+        );
+        assert_eq!(
+            evaluate("9223372036854775807 + 1"),
+            Err(Error::new(ErrorKind::Overflow, "Integer overflow"))
+        );
+        assert_eq!(
+            evaluate("1 / 0"),
+            Err(Error::new(ErrorKind::ZeroDivision, "Division by zero"))
+        );
+        assert_eq!(
+            evaluate("1 % 0"),
+            Err(Error::new(ErrorKind::ZeroDivision, "Division by zero"))
+        );
+        assert_eq!(evaluate("4 == 4.0"), Ok(Val::Bool(true)));
+        assert_eq!(
+            evaluate("9007199254740993 == 9007199254740992.0"),
+            Ok(Val::Bool(false))
+        );
+        assert_eq!(
+            evaluate("9007199254740993 > 9007199254740992.0"),
+            Ok(Val::Bool(true))
+        );
+    }
 }
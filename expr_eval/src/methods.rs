@@ -0,0 +1,346 @@
+use crate::{
+    error::{Error, ErrorKind},
+    val::Val,
+};
+use std::convert::TryInto;
+
+/// Built-in method of a type, called with `receiver.name(arguments)`
+#[derive(Clone, Copy)]
+pub enum Method {
+    Read(fn(&Val, Vec<Val>) -> Result<Val, Error>),
+    Write(fn(&mut Val, Vec<Val>) -> Result<Val, Error>), // Modifies the receiver in place
+}
+
+const VECTOR_METHODS: &[(&str, Method)] = &[
+    ("push", Method::Write(push)),
+    ("pop", Method::Write(pop)),
+    ("insert", Method::Write(insert)),
+    ("remove", Method::Write(remove)),
+    ("reverse", Method::Write(reverse)),
+    ("len", Method::Read(len)),
+    ("contains", Method::Read(contains)),
+    ("join", Method::Read(join)),
+];
+
+const STRING_METHODS: &[(&str, Method)] = &[
+    ("len", Method::Read(len)),
+    ("contains", Method::Read(contains)),
+    ("upper", Method::Read(upper)),
+    ("lower", Method::Read(lower)),
+    ("trim", Method::Read(trim)),
+    ("starts_with", Method::Read(starts_with)),
+    ("ends_with", Method::Read(ends_with)),
+    ("split", Method::Read(split)),
+    ("replace", Method::Read(replace)),
+    ("to_int", Method::Read(to_int)),
+];
+
+const NUMBER_METHODS: &[(&str, Method)] = &[
+    ("abs", Method::Read(abs)),
+    ("floor", Method::Read(floor)),
+    ("ceil", Method::Read(ceil)),
+    ("round", Method::Read(round)),
+    ("sqrt", Method::Read(sqrt)),
+    ("pow", Method::Read(pow)),
+    ("div", Method::Read(div)),
+    ("min", Method::Read(min)),
+    ("max", Method::Read(max)),
+    ("to_int", Method::Read(to_int)),
+    ("to_float", Method::Read(to_float)),
+];
+
+/// Looks up the method `name` in the table of the type of `receiver`
+pub fn find_method(receiver: &Val, name: &str) -> Result<Method, Error> {
+    let table = match receiver {
+        Val::Vec(_) => VECTOR_METHODS,
+        Val::Str(_) => STRING_METHODS,
+        Val::Int(_) | Val::Number(_) => NUMBER_METHODS,
+        _ => &[],
+    };
+    table
+        .iter()
+        .find(|(method, _)| *method == name)
+        .map(|(_, method)| *method)
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::Type,
+                format!("{} has no method {}", receiver.type_name(), name),
+            )
+        })
+}
+
+fn arguments<const N: usize>(arguments: Vec<Val>) -> Result<[Val; N], Error> {
+    arguments
+        .try_into()
+        .map_err(|_| "Wrong number of arguments".into())
+}
+
+fn string_argument(argument: &Val) -> Result<&str, Error> {
+    match argument {
+        Val::Str(s) => Ok(s),
+        _ => Err(Error::new(ErrorKind::Type, "Expected a string argument")),
+    }
+}
+
+fn number_argument(argument: &Val) -> Result<f64, Error> {
+    argument
+        .as_float()
+        .ok_or_else(|| Error::new(ErrorKind::Type, "Expected a number argument"))
+}
+
+/// Position in a vector of `len` elements, `len` itself is only valid when `inclusive`
+fn index_argument(argument: &Val, len: usize, inclusive: bool) -> Result<usize, Error> {
+    let n = match argument {
+        Val::Int(n) => *n,
+        Val::Number(_) => {
+            return Err(Error::new(
+                ErrorKind::Type,
+                "Can't index with a floating point number",
+            ))
+        }
+        _ => return Err(Error::new(ErrorKind::Type, "Expected a number argument")),
+    };
+    match n >= 0 && (n < len as i64 || (inclusive && n == len as i64)) {
+        true => Ok(n as usize),
+        false => Err(Error::new(ErrorKind::Index, "Vector access out of bounds")),
+    }
+}
+
+fn overflow() -> Error {
+    Error::new(ErrorKind::Overflow, "Integer overflow")
+}
+
+// The tables only pair these methods with receivers of their type
+fn items(receiver: &Val) -> &Vec<Val> {
+    match receiver {
+        Val::Vec(v) => v,
+        _ => unreachable!("Vector methods are only called on vectors"),
+    }
+}
+
+fn items_mut(receiver: &mut Val) -> &mut Vec<Val> {
+    match receiver {
+        Val::Vec(v) => v,
+        _ => unreachable!("Vector methods are only called on vectors"),
+    }
+}
+
+fn text(receiver: &Val) -> &str {
+    match receiver {
+        Val::Str(s) => s,
+        _ => unreachable!("String methods are only called on strings"),
+    }
+}
+
+fn number(receiver: &Val) -> f64 {
+    match receiver.as_float() {
+        Some(n) => n,
+        None => unreachable!("Number methods are only called on numbers"),
+    }
+}
+
+fn push(receiver: &mut Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [element] = arguments(args)?;
+    receiver.push(element)?;
+    Ok(Val::Null)
+}
+
+fn pop(receiver: &mut Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [] = arguments(args)?;
+    receiver.pop()
+}
+
+fn insert(receiver: &mut Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [index, element] = arguments(args)?;
+    let v = items_mut(receiver);
+    let index = index_argument(&index, v.len(), true)?;
+    v.insert(index, element);
+    Ok(Val::Null)
+}
+
+fn remove(receiver: &mut Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [index] = arguments(args)?;
+    let v = items_mut(receiver);
+    let index = index_argument(&index, v.len(), false)?;
+    Ok(v.remove(index))
+}
+
+fn reverse(receiver: &mut Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [] = arguments(args)?;
+    items_mut(receiver).reverse();
+    Ok(Val::Null)
+}
+
+fn len(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [] = arguments(args)?;
+    match receiver {
+        Val::Vec(v) => Ok(Val::Int(v.len() as i64)),
+        _ => Ok(Val::Int(text(receiver).chars().count() as i64)),
+    }
+}
+
+fn contains(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [element] = arguments(args)?;
+    Ok(Val::Bool(receiver.contains(&element)?))
+}
+
+fn join(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [separator] = arguments(args)?;
+    let separator = string_argument(&separator)?;
+    let parts = items(receiver)
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>();
+    Ok(Val::Str(parts.join(separator)))
+}
+
+fn upper(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [] = arguments(args)?;
+    Ok(Val::Str(text(receiver).to_uppercase()))
+}
+
+fn lower(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [] = arguments(args)?;
+    Ok(Val::Str(text(receiver).to_lowercase()))
+}
+
+fn trim(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [] = arguments(args)?;
+    Ok(Val::Str(text(receiver).trim().to_owned()))
+}
+
+fn starts_with(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [prefix] = arguments(args)?;
+    Ok(Val::Bool(
+        text(receiver).starts_with(string_argument(&prefix)?),
+    ))
+}
+
+fn ends_with(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [suffix] = arguments(args)?;
+    Ok(Val::Bool(
+        text(receiver).ends_with(string_argument(&suffix)?),
+    ))
+}
+
+fn split(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [separator] = arguments(args)?;
+    let parts = text(receiver).split(string_argument(&separator)?);
+    Ok(Val::Vec(parts.map(|p| Val::Str(p.to_owned())).collect()))
+}
+
+fn replace(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [from, to] = arguments(args)?;
+    let (from, to) = (string_argument(&from)?, string_argument(&to)?);
+    Ok(Val::Str(text(receiver).replace(from, to)))
+}
+
+fn abs(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [] = arguments(args)?;
+    match receiver {
+        Val::Int(n) => n.checked_abs().map(Val::Int).ok_or_else(overflow),
+        _ => Ok(Val::Number(number(receiver).abs())),
+    }
+}
+
+/// Rounding an int leaves it as it is
+fn rounded(receiver: &Val, round: fn(f64) -> f64) -> Val {
+    match receiver {
+        Val::Int(n) => Val::Int(*n),
+        _ => Val::Number(round(number(receiver))),
+    }
+}
+
+fn floor(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [] = arguments(args)?;
+    Ok(rounded(receiver, f64::floor))
+}
+
+fn ceil(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [] = arguments(args)?;
+    Ok(rounded(receiver, f64::ceil))
+}
+
+fn round(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [] = arguments(args)?;
+    Ok(rounded(receiver, f64::round))
+}
+
+fn sqrt(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [] = arguments(args)?;
+    Ok(Val::Number(number(receiver).sqrt()))
+}
+
+/// Powers of ints to non-negative ints are ints, any other power is a float
+fn pow(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [exponent] = arguments(args)?;
+    match (receiver, &exponent) {
+        (Val::Int(base), Val::Int(exponent)) if *exponent >= 0 => (*exponent)
+            .try_into()
+            .ok()
+            .and_then(|exponent: u32| base.checked_pow(exponent))
+            .map(Val::Int)
+            .ok_or_else(overflow),
+        _ => Ok(Val::Number(
+            number(receiver).powf(number_argument(&exponent)?),
+        )),
+    }
+}
+
+/// Division that drops the fractional part of the result, which is an int for two ints
+fn div(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [divisor] = arguments(args)?;
+    match (receiver, &divisor) {
+        (Val::Int(_), Val::Int(0)) => Err(Error::new(ErrorKind::ZeroDivision, "Division by zero")),
+        (Val::Int(a), Val::Int(b)) => a.checked_div(*b).map(Val::Int).ok_or_else(overflow),
+        _ => Ok(Val::Number(
+            (number(receiver) / number_argument(&divisor)?).trunc(),
+        )),
+    }
+}
+
+fn min(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [other] = arguments(args)?;
+    match (receiver, &other) {
+        (Val::Int(a), Val::Int(b)) => Ok(Val::Int(*a.min(b))),
+        _ => Ok(Val::Number(number(receiver).min(number_argument(&other)?))),
+    }
+}
+
+fn max(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [other] = arguments(args)?;
+    match (receiver, &other) {
+        (Val::Int(a), Val::Int(b)) => Ok(Val::Int(*a.max(b))),
+        _ => Ok(Val::Number(number(receiver).max(number_argument(&other)?))),
+    }
+}
+
+/// Drops the fractional part of a float, or parses a string. Ints are left as they are.
+fn to_int(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [] = arguments(args)?;
+    let n = match receiver {
+        Val::Int(n) => return Ok(Val::Int(*n)),
+        Val::Str(s) => {
+            return s.trim().parse().map(Val::Int).map_err(|_| {
+                Error::new(
+                    ErrorKind::Type,
+                    format!("{} can't be converted to an int", receiver.literal()),
+                )
+            })
+        }
+        _ => number(receiver).trunc(),
+    };
+    // 2^63 is the first float out of range, i64::MAX itself isn't representable
+    match n.is_finite() && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+        true => Ok(Val::Int(n as i64)),
+        false => Err(Error::new(
+            ErrorKind::Overflow,
+            format!("{} can't be converted to an int", receiver),
+        )),
+    }
+}
+
+fn to_float(receiver: &Val, args: Vec<Val>) -> Result<Val, Error> {
+    let [] = arguments(args)?;
+    Ok(Val::Number(number(receiver)))
+}
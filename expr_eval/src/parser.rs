@@ -1,21 +1,27 @@
 use crate::val::Val;
 use crate::{
+    error::Error,
     exprtoken_processor::{process_expr_tokens, ProcessedExprToken},
     tokenizer::ExprToken,
 };
-type Error = &'static str;
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum ParseExprNode {
     VarName(String),
     Number(Val),
     String(Val),
+    Interpolation(Vec<ParseExprNode>),
     Bool(Val),
     Null,
-    //FnCallStart(&'a str),
+    FnCall(String, Vec<ParseExprNode>),
+    Call(Box<ParseExprNode>, Vec<ParseExprNode>), //Call(Callee, Arguments)
+    Lambda(Vec<String>, Box<ParseExprNode>),      //Lambda(Parameters, Body)
     VecAccess(String, Vec<ParseExprNode>),
-    //Dot
+    Field(Box<ParseExprNode>, String),    //Field(Value, Field name)
+    Record(Vec<(String, ParseExprNode)>), //Record([(Field name, Value)])
+    Map(Vec<(ParseExprNode, ParseExprNode)>), //Map([(Key, Value)])
     Vector(Vec<ParseExprNode>),
+    Index(Box<ParseExprNode>, Vec<ParseExprNode>), //Index(Value, Indexes), for values not read from a variable
     Neg(Box<ParseExprNode>),
     Mul(Box<[ParseExprNode; 2]>),
     Div(Box<[ParseExprNode; 2]>),
@@ -31,22 +37,136 @@ pub enum ParseExprNode {
     And(Box<[ParseExprNode; 2]>),
     Or(Box<[ParseExprNode; 2]>),
     Not(Box<ParseExprNode>),
+    Range(Box<[ParseExprNode; 3]>), //Range([Start, End, Step])
+    RangeInclusive(Box<[ParseExprNode; 3]>),
+    In(Box<[ParseExprNode; 2]>),
+    Conditional(Box<[ParseExprNode; 3]>), //Conditional([Condition, Value if true, Value if false])
 }
 fn parse_vector(vector: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
     Ok(ParseExprNode::Vector(
         vector
             .split(|x| *x == ProcessedExprToken::Comma)
             .filter(|&x| !x.is_empty())
-            .map(|t| parse_and(t))
+            .map(parse_conditional)
             .collect::<Result<Vec<_>, _>>()?,
     ))
 }
 
+impl ParseExprNode {
+    /// Calls `f` with the name of every variable read inside the expression
+    pub fn for_each_variable(&self, f: &mut impl FnMut(&str)) {
+        match self {
+            ParseExprNode::VarName(name) => f(name),
+            ParseExprNode::FnCall(name, arguments) => {
+                f(name);
+                arguments.iter().for_each(|a| a.for_each_variable(f));
+            }
+            ParseExprNode::VecAccess(name, index) => {
+                f(name);
+                index.iter().for_each(|i| i.for_each_variable(f));
+            }
+            ParseExprNode::Call(callee, arguments) | ParseExprNode::Index(callee, arguments) => {
+                callee.for_each_variable(f);
+                arguments.iter().for_each(|a| a.for_each_variable(f));
+            }
+            ParseExprNode::Lambda(_, body) => body.for_each_variable(f),
+            ParseExprNode::Field(value, _) => value.for_each_variable(f),
+            ParseExprNode::Vector(v) | ParseExprNode::Interpolation(v) => {
+                v.iter().for_each(|e| e.for_each_variable(f))
+            }
+            ParseExprNode::Record(fields) => {
+                fields.iter().for_each(|(_, v)| v.for_each_variable(f))
+            }
+            ParseExprNode::Map(entries) => entries.iter().for_each(|(k, v)| {
+                k.for_each_variable(f);
+                v.for_each_variable(f);
+            }),
+            ParseExprNode::Neg(n) | ParseExprNode::Not(n) => n.for_each_variable(f),
+            ParseExprNode::Mul(s)
+            | ParseExprNode::Div(s)
+            | ParseExprNode::Rem(s)
+            | ParseExprNode::Add(s)
+            | ParseExprNode::Sub(s)
+            | ParseExprNode::Eq(s)
+            | ParseExprNode::NotEq(s)
+            | ParseExprNode::Gt(s)
+            | ParseExprNode::Lt(s)
+            | ParseExprNode::Gtoe(s)
+            | ParseExprNode::Ltoe(s)
+            | ParseExprNode::And(s)
+            | ParseExprNode::Or(s)
+            | ParseExprNode::In(s) => s.iter().for_each(|e| e.for_each_variable(f)),
+            ParseExprNode::Range(r)
+            | ParseExprNode::RangeInclusive(r)
+            | ParseExprNode::Conditional(r) => r.iter().for_each(|e| e.for_each_variable(f)),
+            ParseExprNode::Number(_)
+            | ParseExprNode::String(_)
+            | ParseExprNode::Bool(_)
+            | ParseExprNode::Null => {}
+        }
+    }
+}
+
+fn parse_arguments(arguments: &[ProcessedExprToken]) -> Result<Vec<ParseExprNode>, Error> {
+    arguments
+        .split(|x| *x == ProcessedExprToken::Comma)
+        .filter(|&x| !x.is_empty())
+        .map(parse_conditional)
+        .collect()
+}
+
+fn parse_fn_call(name: &str, arguments: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
+    Ok(ParseExprNode::FnCall(
+        name.to_string(),
+        parse_arguments(arguments)?,
+    ))
+}
+
+fn parse_call(
+    callee: &ProcessedExprToken,
+    arguments: &[ProcessedExprToken],
+) -> Result<ParseExprNode, Error> {
+    Ok(ParseExprNode::Call(
+        Box::new(parse_final_element(callee)?),
+        parse_arguments(arguments)?,
+    ))
+}
+
+fn parse_lambda(
+    parameters: &[String],
+    body: &[ProcessedExprToken],
+) -> Result<ParseExprNode, Error> {
+    Ok(ParseExprNode::Lambda(
+        parameters.to_vec(),
+        Box::new(parse_conditional(body)?),
+    ))
+}
+
+fn parse_record(fields: &[(String, Vec<ProcessedExprToken>)]) -> Result<ParseExprNode, Error> {
+    Ok(ParseExprNode::Record(
+        fields
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), parse_conditional(value)?)))
+            .collect::<Result<Vec<_>, Error>>()?,
+    ))
+}
+
+fn parse_map(
+    entries: &[(Vec<ProcessedExprToken>, Vec<ProcessedExprToken>)],
+) -> Result<ParseExprNode, Error> {
+    Ok(ParseExprNode::Map(
+        entries
+            .iter()
+            .map(|(key, value)| Ok((parse_conditional(key)?, parse_conditional(value)?)))
+            .collect::<Result<Vec<_>, Error>>()?,
+    ))
+}
+
 fn parse_vector_read(
     name: &str,
     index_expr: &[Vec<ProcessedExprToken>],
 ) -> Result<ParseExprNode, Error> {
-    let indexes: Result<Vec<_>, _> = index_expr.iter().map(|e| parse_and(e)).collect();
+    let indexes: Result<Vec<_>, _> = index_expr.iter().map(|e| parse_conditional(e)).collect();
     Ok(ParseExprNode::VecAccess(name.to_string(), indexes?))
 }
 
@@ -58,23 +178,52 @@ fn neg_to_node(a: &ProcessedExprToken) -> Result<ParseExprNode, Error> {
 
 fn parse_final_element(final_element: &ProcessedExprToken) -> Result<ParseExprNode, Error> {
     match final_element {
+        ProcessedExprToken::FnCall(name, arguments) => parse_fn_call(name, arguments),
+        ProcessedExprToken::Call(callee, arguments) => parse_call(callee, arguments),
+        ProcessedExprToken::Lambda(parameters, body) => parse_lambda(parameters, body),
+        ProcessedExprToken::Field(value, name) => Ok(ParseExprNode::Field(
+            Box::new(parse_final_element(value)?),
+            name.clone(),
+        )),
         ProcessedExprToken::VecAccess(name, index_expr) => parse_vector_read(name, index_expr),
+        ProcessedExprToken::Index(value, index_expr) => Ok(ParseExprNode::Index(
+            Box::new(parse_final_element(value)?),
+            index_expr
+                .iter()
+                .map(|e| parse_conditional(e))
+                .collect::<Result<_, _>>()?,
+        )),
         ProcessedExprToken::Vector(v) => parse_vector(v),
+        ProcessedExprToken::Record(fields) => parse_record(fields),
+        ProcessedExprToken::Map(entries) => parse_map(entries),
         ProcessedExprToken::Null => Ok(ParseExprNode::Null),
         ProcessedExprToken::Bool(a) => Ok(ParseExprNode::Bool(Val::Bool(*a))),
         ProcessedExprToken::String(a) => Ok(ParseExprNode::String(Val::Str(a.clone()))),
+        ProcessedExprToken::Interpolation(parts) => Ok(ParseExprNode::Interpolation(
+            parts
+                .iter()
+                .map(|p| parse_conditional(p))
+                .collect::<Result<_, _>>()?,
+        )),
         ProcessedExprToken::Neg(a) => neg_to_node(a),
+        ProcessedExprToken::Not(Some(a)) => {
+            Ok(ParseExprNode::Not(Box::new(parse_final_element(a)?)))
+        }
+        ProcessedExprToken::Int(a) => Ok(ParseExprNode::Number(Val::Int(*a))),
         ProcessedExprToken::Number(a) => Ok(ParseExprNode::Number(Val::Number(*a))),
-        ProcessedExprToken::Parentheses(a) => parse_and(a),
+        ProcessedExprToken::Parentheses(a) => parse_conditional(a),
         ProcessedExprToken::VarName(a) => Ok(ParseExprNode::VarName(a.clone())),
-        _ => Err("Error parsing final element"),
+        _ => Err("Error parsing final element".into()),
     }
 }
 
 fn parse_mul(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
     tokens
         .split(|x| *x == ProcessedExprToken::Mul)
-        .map(|x| parse_final_element(&x[0]))
+        .map(|x| match x {
+            [element] => parse_final_element(element),
+            _ => Err("Error parsing multiplication".into()),
+        })
         .reduce(|a, b| Ok(ParseExprNode::Mul(Box::new([a?, b?]))))
         .ok_or("Error parsing multiplication")?
 }
@@ -82,7 +231,7 @@ fn parse_mul(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
 fn parse_div(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
     tokens
         .split(|x| *x == ProcessedExprToken::Div)
-        .map(|x| parse_mul(x))
+        .map(parse_mul)
         .reduce(|a, b| Ok(ParseExprNode::Div(Box::new([a?, b?]))))
         .ok_or("Error parsing division")?
 }
@@ -90,7 +239,7 @@ fn parse_div(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
 fn parse_rem(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
     tokens
         .split(|x| *x == ProcessedExprToken::Rem)
-        .map(|x| parse_div(x))
+        .map(parse_div)
         .reduce(|a, b| Ok(ParseExprNode::Rem(Box::new([a?, b?]))))
         .ok_or("Error parsing division")?
 }
@@ -98,7 +247,7 @@ fn parse_rem(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
 fn parse_sub(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
     tokens
         .split(|x| *x == ProcessedExprToken::Sub)
-        .map(|x| parse_rem(x))
+        .map(parse_rem)
         .reduce(|a, b| Ok(ParseExprNode::Sub(Box::new([a?, b?]))))
         .ok_or("Error parsing subtraction")?
 }
@@ -106,15 +255,56 @@ fn parse_sub(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
 fn parse_add(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
     tokens
         .split(|x| *x == ProcessedExprToken::Add)
-        .map(|x| parse_sub(x))
+        .map(parse_sub)
         .reduce(|a, b| Ok(ParseExprNode::Add(Box::new([a?, b?]))))
         .ok_or("Error parsing subtraction")?
 }
 
+/// A missing range limit, as in `v[2..]`, is left to the evaluator
+fn parse_range_limit(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
+    if tokens.is_empty() {
+        Ok(ParseExprNode::Null)
+    } else {
+        parse_add(tokens)
+    }
+}
+
+fn parse_range(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
+    let mut parts = tokens.splitn(2, |x| *x == ProcessedExprToken::Step);
+    let range = parts.next().ok_or("Error parsing range")?;
+    let step = parts.next().map(parse_add).transpose()?;
+    let operator = range
+        .iter()
+        .position(|x| *x == ProcessedExprToken::Range || *x == ProcessedExprToken::RangeInclusive);
+    match (operator, step) {
+        (Some(p), step) => {
+            let limits = Box::new([
+                parse_range_limit(&range[..p])?,
+                parse_range_limit(&range[p + 1..])?,
+                step.unwrap_or(ParseExprNode::Number(Val::Int(1))),
+            ]);
+            match range[p] {
+                ProcessedExprToken::Range => Ok(ParseExprNode::Range(limits)),
+                _ => Ok(ParseExprNode::RangeInclusive(limits)),
+            }
+        }
+        (None, Some(_)) => Err("step can only be used with ranges".into()),
+        (None, None) => parse_add(range),
+    }
+}
+
+fn parse_in(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
+    tokens
+        .split(|x| *x == ProcessedExprToken::In)
+        .map(parse_range)
+        .reduce(|a, b| Ok(ParseExprNode::In(Box::new([a?, b?]))))
+        .ok_or("Error parsing in operator")?
+}
+
 fn parse_ltoe(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
     tokens
         .split(|x| *x == ProcessedExprToken::Ltoe)
-        .map(|x| parse_add(x))
+        .map(parse_in)
         .reduce(|a, b| Ok(ParseExprNode::Ltoe(Box::new([a?, b?]))))
         .ok_or("Error parsing addition")?
 }
@@ -122,7 +312,7 @@ fn parse_ltoe(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
 fn parse_lt(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
     tokens
         .split(|x| *x == ProcessedExprToken::Lt)
-        .map(|x| parse_ltoe(x))
+        .map(parse_ltoe)
         .reduce(|a, b| Ok(ParseExprNode::Lt(Box::new([a?, b?]))))
         .ok_or("Error parsing addition")?
 }
@@ -130,7 +320,7 @@ fn parse_lt(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
 fn parse_gtoe(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
     tokens
         .split(|x| *x == ProcessedExprToken::Gtoe)
-        .map(|x| parse_lt(x))
+        .map(parse_lt)
         .reduce(|a, b| Ok(ParseExprNode::Gtoe(Box::new([a?, b?]))))
         .ok_or("Error parsing addition")?
 }
@@ -138,7 +328,7 @@ fn parse_gtoe(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
 fn parse_gt(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
     tokens
         .split(|x| *x == ProcessedExprToken::Gt)
-        .map(|x| parse_gtoe(x))
+        .map(parse_gtoe)
         .reduce(|a, b| Ok(ParseExprNode::Gt(Box::new([a?, b?]))))
         .ok_or("Error parsing addition")?
 }
@@ -146,7 +336,7 @@ fn parse_gt(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
 fn parse_noteq(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
     tokens
         .split(|x| *x == ProcessedExprToken::NotEq)
-        .map(|x| parse_gt(x))
+        .map(parse_gt)
         .reduce(|a, b| Ok(ParseExprNode::NotEq(Box::new([a?, b?]))))
         .ok_or("Error parsing addition")?
 }
@@ -154,7 +344,7 @@ fn parse_noteq(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
 fn parse_eq(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
     tokens
         .split(|x| *x == ProcessedExprToken::Eq)
-        .map(|x| parse_noteq(x))
+        .map(parse_noteq)
         .reduce(|a, b| Ok(ParseExprNode::Eq(Box::new([a?, b?]))))
         .ok_or("Error parsing addition")?
 }
@@ -162,7 +352,7 @@ fn parse_eq(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
 fn parse_or(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
     tokens
         .split(|x| *x == ProcessedExprToken::Or)
-        .map(|x| parse_eq(x))
+        .map(parse_eq)
         .reduce(|a, b| Ok(ParseExprNode::Or(Box::new([a?, b?]))))
         .ok_or("Error parsing logical or")?
 }
@@ -170,13 +360,54 @@ fn parse_or(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
 fn parse_and(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
     tokens
         .split(|x| *x == ProcessedExprToken::And)
-        .map(|x| parse_or(x))
+        .map(parse_or)
         .reduce(|a, b| Ok(ParseExprNode::And(Box::new([a?, b?]))))
         .ok_or("Error parsing logical and")?
 }
 
+/// Parses `condition ? value : other_value`, which groups from the right
+fn parse_conditional(tokens: &[ProcessedExprToken]) -> Result<ParseExprNode, Error> {
+    let question = match tokens
+        .iter()
+        .position(|x| *x == ProcessedExprToken::Question)
+    {
+        Some(q) => q,
+        None => return parse_and(tokens),
+    };
+    let branches = &tokens[question + 1..];
+    let mut nested = 0;
+    let colon = branches
+        .iter()
+        .position(|x| match x {
+            ProcessedExprToken::Question => {
+                nested += 1;
+                false
+            }
+            ProcessedExprToken::Colon if nested == 0 => true,
+            ProcessedExprToken::Colon => {
+                nested -= 1;
+                false
+            }
+            _ => false,
+        })
+        .ok_or("Expected : in conditional expression")?;
+    let (condition, if_true, if_false) = (
+        &tokens[..question],
+        &branches[..colon],
+        &branches[colon + 1..],
+    );
+    if condition.is_empty() || if_true.is_empty() || if_false.is_empty() {
+        return Err("Missing operand in conditional expression".into());
+    }
+    Ok(ParseExprNode::Conditional(Box::new([
+        parse_and(condition)?,
+        parse_conditional(if_true)?,
+        parse_conditional(if_false)?,
+    ])))
+}
+
 pub fn parse_expr(tokens: &[ExprToken]) -> Result<ParseExprNode, Error> {
     let processed_tokens = process_expr_tokens(tokens)?;
     // dbg!(&processed_tokens);
-    parse_and(&processed_tokens)
+    parse_conditional(&processed_tokens)
 }
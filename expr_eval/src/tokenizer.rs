@@ -1,19 +1,26 @@
+use crate::error::{Error, ErrorKind};
 use regex::Regex;
+use std::borrow::Cow;
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum ExprToken {
     VarName(String),
+    Int(i64),
     Number(f64),
     String(String),
     Bool(bool),
     Null,
-    //FnCallStart(&'a str),
+    FnCallStart(String),
     VecAccessStart(String),
-    //Dot
+    Dot,
     OpenSBrackets,
     CloseSBrackets,
     OpenParentheses,
     CloseParentheses,
+    OpenCBrackets,
+    CloseCBrackets,
+    Colon,
+    Question,
     Mul,
     Div,
     Rem,
@@ -29,38 +36,171 @@ pub enum ExprToken {
     Or,
     Not,
     Comma,
+    Pipe,
+    Range,
+    RangeInclusive,
+    Step,
+    In,
 }
 
-fn check_remaining_cases(capture: &str) -> Result<ExprToken, &'static str> {
+fn check_remaining_cases(capture: &str) -> Result<ExprToken, Error> {
     lazy_static! {
         static ref VAR_REGEX: Regex = Regex::new(r"[^\{\}\n=\(\)\[\]]").unwrap();
-        static ref VEC_ACCESS_REGEX: Regex = Regex::new(r"([[:alpha:]]|\])+\[").unwrap();
+        static ref VEC_ACCESS_REGEX: Regex = Regex::new(r"([[:alpha:]_][[:word:]]*|\])\[").unwrap();
+        static ref FN_CALL_REGEX: Regex = Regex::new(r"[[:alpha:]_][[:word:]]*\(").unwrap();
     }
-    if let Ok(n) = capture.parse::<f64>() {
+    if capture.bytes().all(|b| b.is_ascii_digit()) {
+        match capture.parse::<i64>() {
+            Ok(n) => Ok(ExprToken::Int(n)),
+            Err(_) => Err(Error::new(
+                ErrorKind::Overflow,
+                format!("Integer literal {} is too large", capture),
+            )),
+        }
+    } else if let Ok(n) = capture.parse::<f64>() {
         Ok(ExprToken::Number(n))
-    } else if capture.starts_with('"') && capture.ends_with('"') {
-        Ok(ExprToken::String(capture.to_owned()))
+    } else if FN_CALL_REGEX.is_match(capture) {
+        Ok(ExprToken::FnCallStart(
+            capture.trim_end_matches('(').to_owned(),
+        ))
     } else if VEC_ACCESS_REGEX.is_match(capture) {
         Ok(ExprToken::VecAccessStart(capture.to_owned()))
     } else if VAR_REGEX.is_match(capture) {
         Ok(ExprToken::VarName(capture.to_owned()))
     } else {
-        Err("Unable to match expression")
+        Err("Unable to match expression".into())
     }
 }
 
-pub fn tokenize_expr(expr: &str) -> Result<Vec<ExprToken>, &'static str> {
+/// Length of the comment at the start of `text`, if there is one.
+/// Block comments can be nested.
+pub fn comment_len(text: &str) -> Result<Option<usize>, Error> {
+    if text.starts_with("//") {
+        return Ok(Some(text.find('\n').unwrap_or(text.len())));
+    }
+    if !text.starts_with("/*") {
+        return Ok(None);
+    }
+    let mut depth = 0;
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        if text[i..].starts_with("/*") {
+            depth += 1;
+            i += 2;
+        } else if text[i..].starts_with("*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return Ok(Some(i));
+            }
+        } else {
+            i += c.len_utf8();
+        }
+    }
+    Err("Unterminated block comment".into())
+}
+
+/// Whether a string literal starts at `i`: `"text"`, `"""text"""`, `r"text"` or `r#"text"#`
+pub fn string_at(text: &str, i: usize) -> bool {
+    let is_raw = match text[i..].strip_prefix('r') {
+        Some(raw) => raw.trim_start_matches('#').starts_with('"'),
+        None => false,
+    };
+    text[i..].starts_with('"')
+        || (is_raw && !text[..i].ends_with(|c: char| c.is_alphanumeric() || c == '_'))
+}
+
+/// Length of the string literal at the start of `text`, including its delimiters. Interpolated
+/// expressions can contain their own strings. Returns `None` if the string isn't closed, which
+/// for strings between single quotes must happen before the end of the line.
+pub fn string_len(text: &str) -> Option<usize> {
+    if let Some(raw) = text.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let closing = format!("\"{}", "#".repeat(hashes));
+        let start = hashes + 2;
+        return text[start..]
+            .find(&closing)
+            .map(|end| start + end + closing.len());
+    }
+    let quotes = if text.starts_with(r#"""""#) {
+        r#"""""#
+    } else {
+        "\""
+    };
+    let mut i = quotes.len();
+    while let Some(c) = text[i..].chars().next() {
+        match c {
+            '"' if text[i..].starts_with(quotes) => return Some(i + quotes.len()),
+            '\n' if quotes.len() == 1 => return None,
+            '\\' => i += 1 + text[i + 1..].chars().next()?.len_utf8(),
+            '$' if text[i..].starts_with("${") => i += 2 + interpolation_len(&text[i + 2..])?,
+            _ => i += c.len_utf8(),
+        }
+    }
+    None
+}
+
+/// Length of the expression inside `${` and `}`, including the closing bracket
+pub(crate) fn interpolation_len(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        match c {
+            _ if string_at(text, i) => i += string_len(&text[i..])?,
+            '{' => {
+                depth += 1;
+                i += 1;
+            }
+            '}' if depth == 0 => return Some(i + 1),
+            '}' => {
+                depth -= 1;
+                i += 1;
+            }
+            _ => i += c.len_utf8(),
+        }
+    }
+    None
+}
+
+/// Replaces the comments outside of string literals with spaces
+fn remove_comments(expr: &str) -> Result<Cow<'_, str>, Error> {
+    if !expr.contains("//") && !expr.contains("/*") {
+        return Ok(Cow::Borrowed(expr));
+    }
+    let mut without_comments = String::with_capacity(expr.len());
+    let mut i = 0;
+    while let Some(c) = expr[i..].chars().next() {
+        let len = match c {
+            _ if string_at(expr, i) => string_len(&expr[i..]).ok_or("Unterminated string")?,
+            '/' => match comment_len(&expr[i..])? {
+                Some(len) => {
+                    without_comments.push(' ');
+                    i += len;
+                    continue;
+                }
+                None => 1,
+            },
+            _ => c.len_utf8(),
+        };
+        without_comments.push_str(&expr[i..i + len]);
+        i += len;
+    }
+    Ok(Cow::Owned(without_comments))
+}
+
+/// Tokenizes code that doesn't contain string literals
+fn tokenize_code(code: &str, tokens: &mut Vec<ExprToken>) -> Result<(), Error> {
     lazy_static! {
         static ref PATTERNS : String = [
-            r"\d+\.?\d*",             //Number
-
-            r"\s*true\s*|\s*true\s*", //Bool
-            r"[^\w\d]null[^\w\d]",  //Null
-            //r"[^\{\}\n=]\(",        //Starting part of a function call
-            r"([[:alpha:]]|\])+\[",        //Starting part of a vector access
-            //r"\."                   //Dot operator
+            r"\d+(\.\d+)?",             //Number
+            r"[[:alpha:]_][[:word:]]*\(",  //Starting part of a function call
+            r"([[:alpha:]_][[:word:]]*|\])\[",        //Starting part of a vector access
+            r"[[:alpha:]_][[:word:]]*", //Variable, bool or null
             r"\(|\)",          // Parentheses
             r"\[|\]",          //Square brackets
+            r"\{|\}",          //Curly brackets
+            r":",             //Colon
+            r"\?",             //Conditional operator
             r"\*",             // Multiplication operator
             r"/",             // Division operator
             r"%",             //Remainder operator
@@ -74,25 +214,37 @@ pub fn tokenize_expr(expr: &str) -> Result<Vec<ExprToken>, &'static str> {
             r">",             //Greater than operator
             r"&&",             //Logical and operator
             r"\|\|",             //Logical or operator
+            r"\|",             //Lambda parameters delimiter
             r",",             //Comma operator
-            r#""[^"\n]*""#,                //String
+            r"\.\.=",             //Inclusive range operator
+            r"\.\.",             //Range operator
+            r"\.",             //Dot operator
             r"[^\{\}\n=\(\)\[\]]", //Variable
         ]
         .join("|");
         static ref EXPR_REGEX: Regex = Regex::new(&PATTERNS).unwrap();
     }
-    EXPR_REGEX
-        .find_iter(expr)
-        .map(|m| expr[m.start()..m.end()].trim())
+    for token in EXPR_REGEX
+        .find_iter(code)
+        .map(|m| code[m.start()..m.end()].trim())
         .filter(|&s| !s.is_empty())
         .map(|capture| match capture {
             "true" => Ok(ExprToken::Bool(true)),
             "false" => Ok(ExprToken::Bool(false)),
             "null" => Ok(ExprToken::Null),
+            "step" => Ok(ExprToken::Step),
+            "in" => Ok(ExprToken::In),
+            ".." => Ok(ExprToken::Range),
+            "..=" => Ok(ExprToken::RangeInclusive),
+            "." => Ok(ExprToken::Dot),
             "(" => Ok(ExprToken::OpenParentheses),
             ")" => Ok(ExprToken::CloseParentheses),
             "[" => Ok(ExprToken::OpenSBrackets),
             "]" => Ok(ExprToken::CloseSBrackets),
+            "{" => Ok(ExprToken::OpenCBrackets),
+            "}" => Ok(ExprToken::CloseCBrackets),
+            ":" => Ok(ExprToken::Colon),
+            "?" => Ok(ExprToken::Question),
             "*" => Ok(ExprToken::Mul),
             "+" => Ok(ExprToken::Add),
             "-" => Ok(ExprToken::Sub),
@@ -101,6 +253,7 @@ pub fn tokenize_expr(expr: &str) -> Result<Vec<ExprToken>, &'static str> {
             "&&" => Ok(ExprToken::And),
             "||" => Ok(ExprToken::Or),
             "," => Ok(ExprToken::Comma),
+            "|" => Ok(ExprToken::Pipe),
             "!" => Ok(ExprToken::Not),
             "/" => Ok(ExprToken::Div),
             "%" => Ok(ExprToken::Rem),
@@ -110,5 +263,22 @@ pub fn tokenize_expr(expr: &str) -> Result<Vec<ExprToken>, &'static str> {
             ">" => Ok(ExprToken::Gt),
             _ => check_remaining_cases(capture),
         })
-        .collect()
+    {
+        tokens.push(token?);
+    }
+    Ok(())
+}
+
+pub fn tokenize_expr(expr: &str) -> Result<Vec<ExprToken>, Error> {
+    let without_comments = remove_comments(expr)?;
+    let mut rest = without_comments.as_ref();
+    let mut tokens = vec![];
+    while let Some((start, _)) = rest.char_indices().find(|&(i, _)| string_at(rest, i)) {
+        tokenize_code(&rest[..start], &mut tokens)?;
+        let len = string_len(&rest[start..]).ok_or("Unterminated string")?;
+        tokens.push(ExprToken::String(rest[start..start + len].to_owned()));
+        rest = &rest[start + len..];
+    }
+    tokenize_code(rest, &mut tokens)?;
+    Ok(tokens)
 }
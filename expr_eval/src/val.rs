@@ -1,33 +1,411 @@
-use std::{cmp::Ordering, fmt, str::FromStr};
-type Error = &'static str;
+use crate::{
+    error::{Error, ErrorKind},
+    evaluator::Function,
+};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fmt, ptr,
+    rc::Rc,
+    str::FromStr,
+};
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub enum Val {
     Bool(bool),
-    Number(f64),
+    Int(i64),
+    Number(f64), // Floating point number
     Str(String),
     Vec(Vec<Val>),
+    Function(Rc<dyn Function>),
+    Range(Range),
+    Error(Box<Error>),
+    Module(Rc<Module>),
+    Record(Record),
+    Map(Map),
+    Variant(Variant),
     Null,
 }
 
+/// Limits of a range of numbers of type `T`
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Limits<T> {
+    pub start: T,
+    pub end: Option<T>, // None when the range has no end
+    pub step: T,
+    pub inclusive: bool,
+}
+
+impl<T: PartialOrd + Default + Copy> Limits<T> {
+    fn within_end(&self, n: T) -> bool {
+        let end = match self.end {
+            Some(end) => end,
+            None => return true,
+        };
+        match (self.step > T::default(), self.inclusive) {
+            (true, false) => n < end,
+            (true, true) => n <= end,
+            (false, false) => n > end,
+            (false, true) => n >= end,
+        }
+    }
+}
+
+/// Lazy sequence of numbers created by `start..end`, `start..=end` or `start..end step n`.
+/// It is a range of ints when its limits and its step are ints, and a range of floats otherwise.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Range {
+    Int(Limits<i64>),
+    Float(Limits<f64>),
+}
+
+impl Range {
+    pub fn new(
+        start: &Val,
+        end: Option<&Val>,
+        step: &Val,
+        inclusive: bool,
+    ) -> Result<Range, Error> {
+        let range = match (start, end, step) {
+            (Val::Int(start), None | Some(Val::Int(_)), Val::Int(step)) => Range::Int(Limits {
+                start: *start,
+                end: end.and_then(|end| end.as_int()),
+                step: *step,
+                inclusive,
+            }),
+            _ => {
+                let number = |n: &Val| {
+                    n.as_float().ok_or_else(|| {
+                        Error::new(ErrorKind::Type, "Range limits and step must be numbers")
+                    })
+                };
+                Range::Float(Limits {
+                    start: number(start)?,
+                    end: end.map(number).transpose()?,
+                    step: number(step)?,
+                    inclusive,
+                })
+            }
+        };
+        match range {
+            Range::Int(Limits { step: 0, .. }) => Err("Range step can't be zero".into()),
+            Range::Float(l) if l.step == 0.0 => Err("Range step can't be zero".into()),
+            _ => Ok(range),
+        }
+    }
+
+    /// Ints are stepped exactly and stop before overflowing, floats are computed
+    /// from the start so that the rounding errors of the steps don't add up
+    pub fn iter(self) -> Box<dyn Iterator<Item = Val>> {
+        match self {
+            Range::Int(l) => Box::new(
+                std::iter::successors(Some(l.start), move |n| n.checked_add(l.step))
+                    .take_while(move |n| l.within_end(*n))
+                    .map(Val::Int),
+            ),
+            Range::Float(l) => Box::new(
+                (0u64..)
+                    .map(move |i| l.start + i as f64 * l.step)
+                    .take_while(move |n| l.within_end(*n))
+                    .map(Val::Number),
+            ),
+        }
+    }
+
+    pub fn contains(&self, n: &Val) -> bool {
+        match (self, n.as_int()) {
+            (Range::Int(l), Some(n)) => {
+                let offset = i128::from(n) - i128::from(l.start);
+                let step = i128::from(l.step);
+                offset % step == 0 && offset / step >= 0 && l.within_end(n)
+            }
+            (Range::Int(_), None) => false,
+            (Range::Float(l), _) => n.as_float().is_some_and(|n| {
+                let steps = (n - l.start) / l.step;
+                steps >= 0.0 && steps.fract() == 0.0 && l.within_end(n)
+            }),
+        }
+    }
+
+    /// Positions selected by the range in a sequence of `len` elements.
+    /// An open end stops at the end of the sequence.
+    fn indexes(self, len: usize) -> Result<Vec<usize>, Error> {
+        let limits = match self {
+            Range::Int(l) => l,
+            Range::Float(_) => {
+                return Err(Error::new(
+                    ErrorKind::Type,
+                    "Can't index with a floating point number",
+                ))
+            }
+        };
+        let bounded = match (limits.end, limits.step > 0) {
+            (Some(_), _) => limits,
+            (None, true) => Limits {
+                end: Some(len as i64),
+                inclusive: false,
+                ..limits
+            },
+            (None, false) => Limits {
+                end: Some(-1),
+                inclusive: false,
+                ..limits
+            },
+        };
+        Range::Int(bounded)
+            .iter()
+            .map(|n| match n {
+                Val::Int(n) if n >= 0 && (n as u64) < len as u64 => Ok(n as usize),
+                _ => Err(Error::new(ErrorKind::Index, "Vector access out of bounds")),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let (start, end, step, inclusive) = match self {
+            Range::Int(l) => (
+                Val::Int(l.start),
+                l.end.map(Val::Int),
+                Val::Int(l.step),
+                l.inclusive,
+            ),
+            Range::Float(l) => (
+                Val::Number(l.start),
+                l.end.map(Val::Number),
+                Val::Number(l.step),
+                l.inclusive,
+            ),
+        };
+        write!(fmt, "{}..", start)?;
+        if inclusive {
+            write!(fmt, "=")?;
+        }
+        if let Some(end) = end {
+            write!(fmt, "{}", end)?;
+        }
+        if step != Val::Int(1) {
+            write!(fmt, " step {}", step)?;
+        }
+        Ok(())
+    }
+}
+
+/// Value with named fields, created by a record literal such as `{x: 1, y: 2}`
+/// or by the constructor of a struct
+#[derive(PartialEq, Debug, Clone)]
+pub struct Record {
+    pub name: Option<String>, // Name of the struct, None for record literals
+    pub fields: Vec<(String, Val)>,
+}
+
+impl Record {
+    pub fn get(&self, field: &str) -> Option<&Val> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, v)| v)
+    }
+
+    pub fn get_mut(&mut self, field: &str) -> Option<&mut Val> {
+        self.fields
+            .iter_mut()
+            .find(|(name, _)| name == field)
+            .map(|(_, v)| v)
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = &self.name {
+            write!(fmt, "{} ", name)?;
+        }
+        let fields = self
+            .fields
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect::<Vec<_>>();
+        write!(fmt, "{{{}}}", fields.join(", "))
+    }
+}
+
+/// Value of an enum declared with `enum Shape { Circle(r), Rect(w, h) }`, such as `Circle(2)`
+#[derive(PartialEq, Debug, Clone)]
+pub struct Variant {
+    pub enum_name: String,
+    pub name: String,
+    pub payload: Vec<(String, Val)>, // Values with the names given to them in the enum
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.name)?;
+        if !self.payload.is_empty() {
+            let values = self
+                .payload
+                .iter()
+                .map(|(_, value)| value.to_string())
+                .collect::<Vec<_>>();
+            write!(fmt, "({})", values.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Hashable form of the values that can be map keys
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+enum Key {
+    Bool(bool),
+    Int(i64),    // Ints and the floats equal to them
+    Number(u64), // Bits of the number, with -0 stored as 0
+    Str(String),
+}
+
+impl Key {
+    fn new(value: &Val) -> Result<Key, Error> {
+        // Floats equal to an int are the same key as the int
+        if let Some(n) = value.as_int() {
+            return Ok(Key::Int(n));
+        }
+        match value {
+            Val::Bool(b) => Ok(Key::Bool(*b)),
+            Val::Number(n) if n.is_nan() => {
+                Err(Error::new(ErrorKind::Type, "NaN can't be a map key"))
+            }
+            Val::Number(n) => Ok(Key::Number((n + 0.0).to_bits())),
+            Val::Str(s) => Ok(Key::Str(s.clone())),
+            _ => Err(Error::new(
+                ErrorKind::Type,
+                "Map keys must be strings, numbers or booleans",
+            )),
+        }
+    }
+}
+
+/// Dictionary created by a literal such as `{"a": 1, 2: true}`. Its entries
+/// keep the order in which they were inserted.
+#[derive(Debug, Clone, Default)]
+pub struct Map {
+    entries: Vec<(Val, Val)>,
+    positions: HashMap<Key, usize>,
+}
+
+impl Map {
+    pub fn get(&self, key: &Val) -> Result<Option<&Val>, Error> {
+        let position = self.positions.get(&Key::new(key)?);
+        Ok(position.map(|&i| &self.entries[i].1))
+    }
+
+    pub fn get_mut(&mut self, key: &Val) -> Result<Option<&mut Val>, Error> {
+        let position = self.positions.get(&Key::new(key)?).copied();
+        Ok(position.map(move |i| &mut self.entries[i].1))
+    }
+
+    /// Replaces the value of `key`, or adds it at the end if the map doesn't have it
+    pub fn insert(&mut self, key: Val, value: Val) -> Result<(), Error> {
+        match self.get_mut(&key)? {
+            Some(current) => *current = value,
+            None => {
+                self.positions.insert(Key::new(&key)?, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Entries in insertion order
+    pub fn entries(&self) -> &[(Val, Val)] {
+        &self.entries
+    }
+}
+
+/// Maps are equal if they have the same entries, in any order
+impl PartialEq for Map {
+    fn eq(&self, other: &Map) -> bool {
+        self.entries.len() == other.entries.len()
+            && self
+                .entries
+                .iter()
+                .all(|(key, value)| other.get(key) == Ok(Some(value)))
+    }
+}
+
+impl fmt::Display for Map {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let entries = self
+            .entries
+            .iter()
+            .map(|(key, value)| format!("{}: {}", key.literal(), value.literal()))
+            .collect::<Vec<_>>();
+        write!(fmt, "{{{}}}", entries.join(", "))
+    }
+}
+
+/// Top level bindings of an imported script, read with `module.name`.
+/// They are copied when the module is loaded and can't be assigned.
+#[derive(PartialEq, Debug)]
+pub struct Module {
+    pub name: String,
+    pub members: BTreeMap<String, Val>,
+}
+
+/// Functions are only equal to themselves
+impl PartialEq for dyn Function {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::addr_eq(self, other)
+    }
+}
+
+/// Ints and floats are equal when they have exactly the same value
+impl PartialEq for Val {
+    fn eq(&self, other: &Val) -> bool {
+        match (self, other) {
+            (Val::Bool(a), Val::Bool(b)) => a == b,
+            (Val::Int(a), Val::Int(b)) => a == b,
+            (Val::Number(a), Val::Number(b)) => a == b,
+            (Val::Int(a), Val::Number(_)) => other.as_int() == Some(*a),
+            (Val::Number(_), Val::Int(b)) => self.as_int() == Some(*b),
+            (Val::Str(a), Val::Str(b)) => a == b,
+            (Val::Vec(a), Val::Vec(b)) => a == b,
+            (Val::Function(a), Val::Function(b)) => a == b,
+            (Val::Range(a), Val::Range(b)) => a == b,
+            (Val::Error(a), Val::Error(b)) => a == b,
+            (Val::Module(a), Val::Module(b)) => a == b,
+            (Val::Record(a), Val::Record(b)) => a == b,
+            (Val::Map(a), Val::Map(b)) => a == b,
+            (Val::Variant(a), Val::Variant(b)) => a == b,
+            (Val::Null, Val::Null) => true,
+            _ => false,
+        }
+    }
+}
+
 impl PartialOrd for Val {
     fn partial_cmp(&self, other: &Val) -> Option<Ordering> {
         match (self, other) {
-            (Val::Number(a), Val::Number(b)) => a.partial_cmp(b),
-            _ => None,
+            (Val::Int(a), Val::Int(b)) => a.partial_cmp(b),
+            // Floats without fractional part are compared as ints to keep large ints exact
+            (Val::Int(_), Val::Number(_)) | (Val::Number(_), Val::Int(_)) => {
+                match (self.as_int(), other.as_int()) {
+                    (Some(a), Some(b)) => a.partial_cmp(&b),
+                    _ => self.as_float()?.partial_cmp(&other.as_float()?),
+                }
+            }
+            _ => self.as_float()?.partial_cmp(&other.as_float()?),
         }
     }
 }
 
 impl FromStr for Val {
-    type Err = &'static str;
+    type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let res = match s.trim() {
             "true" => Ok(Val::Bool(true)),
             "false" => Ok(Val::Bool(false)),
             "Null" => Ok(Val::Null),
             st if st.starts_with('"') && st.ends_with('"') => Ok(Val::Str(st.to_string())),
-            _ => Err("Error while parsing Value"),
+            _ => Err("Error while parsing Value".into()),
         };
         parse_f64_and_vec(s, res)
     }
@@ -35,6 +413,9 @@ impl FromStr for Val {
 
 fn parse_f64_and_vec(s: &str, res: Result<Val, Error>) -> Result<Val, Error> {
     if res.is_err() {
+        if let Ok(n) = s.parse::<i64>() {
+            return Ok(Val::Int(n));
+        }
         match s.parse::<f64>().map_err(|_| "Error parsing number") {
             Ok(n) => Ok(Val::Number(n)),
             Err(_) => match s {
@@ -56,13 +437,16 @@ fn to_vec(s: &str) -> Result<Val, Error> {
                 .collect::<Result<Vec<Val>, Error>>()?,
         ))
     } else {
-        Err("Error while parsing Vector")
+        Err("Error while parsing Vector".into())
     }
 }
 impl fmt::Display for Val {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         let val = match self {
             Val::Bool(b) => b.to_string(),
+            Val::Int(n) => n.to_string(),
+            // Floats always show a decimal point to tell them apart from ints
+            Val::Number(f) if f.is_finite() && f.fract() == 0.0 => format!("{:.1}", f),
             Val::Number(f) => f.to_string(),
             Val::Str(s) => s.clone(),
             Val::Vec(v) => {
@@ -73,136 +457,376 @@ impl fmt::Display for Val {
                         .join(", ")
                     + "]"
             }
+            Val::Function(_) => "<function>".to_string(),
+            Val::Range(r) => r.to_string(),
+            Val::Error(e) => format!("{}: {}", e.kind(), e.message()),
+            Val::Module(m) => format!("<module {}>", m.name),
+            Val::Record(r) => r.to_string(),
+            Val::Map(m) => m.to_string(),
+            Val::Variant(v) => v.to_string(),
             Val::Null => "Null".to_string(),
         };
         write!(fmt, "{}", val)
     }
 }
 
+/// Position of the element of a vector of `len` elements selected by the index `i`
+fn vector_position(i: &Val, len: usize) -> Result<usize, Error> {
+    match i {
+        Val::Int(n) if *n >= 0 && (*n as u64) < len as u64 => Ok(*n as usize),
+        Val::Int(_) => Err(Error::new(ErrorKind::Index, "Vector access out of bounds")),
+        Val::Number(_) => Err(Error::new(
+            ErrorKind::Type,
+            "Can't index with a floating point number",
+        )),
+        _ => Err(Error::new(ErrorKind::Type, "Index must be a number")),
+    }
+}
+
+fn no_field(type_name: &str, name: &str) -> Error {
+    Error::new(
+        ErrorKind::Type,
+        format!("{} has no field {}", type_name, name),
+    )
+}
+
+/// Members of a module are the values it had when it was imported, so they are read only
+fn module_member_write(member: &Val) -> Error {
+    Error::new(
+        ErrorKind::Type,
+        format!("Cannot assign to module member {}", member),
+    )
+}
+
+#[allow(clippy::should_implement_trait)]
 impl Val {
+    /// Form of the value used for the keys and values of maps. It is the same as its
+    /// display, except for strings, which are quoted and escaped like literals.
+    pub fn literal(&self) -> String {
+        match self {
+            Val::Str(s) => format!("{:?}", s).replace("${", "\\${"),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Value of an int, or of a float without fractional part that fits in an int
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Val::Int(n) => Some(*n),
+            // i64::MAX isn't representable as a float, 2^63 is the first float out of range
+            Val::Number(n) if n.fract() == 0.0 && *n >= i64::MIN as f64 && *n < i64::MAX as f64 => {
+                Some(*n as i64)
+            }
+            _ => None,
+        }
+    }
+
+    /// Value of a number as a float
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Val::Int(n) => Some(*n as f64),
+            Val::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Applies an arithmetic operator to two numbers. Ints use the checked version of the
+    /// operator, and an int combined with a float is converted to a float.
+    fn arithmetic(
+        self,
+        other: Self,
+        int: fn(i64, i64) -> Option<i64>,
+        float: fn(f64, f64) -> f64,
+        type_error: &'static str,
+    ) -> Result<Self, Error> {
+        match (&self, &other) {
+            (Val::Int(a), Val::Int(b)) => int(*a, *b)
+                .map(Val::Int)
+                .ok_or_else(|| Error::new(ErrorKind::Overflow, "Integer overflow")),
+            _ => match (self.as_float(), other.as_float()) {
+                (Some(a), Some(b)) => Ok(Val::Number(float(a, b))),
+                _ => Err(Error::new(ErrorKind::Type, type_error)),
+            },
+        }
+    }
+
     pub fn add(self, other: Self) -> Result<Self, Error> {
         match (self, other) {
-            (Val::Number(a), Val::Number(b)) => Ok(Val::Number(a + b)),
             (Val::Str(a), Val::Str(b)) => Ok(Val::Str(a + &b)),
             (Val::Str(a), b) => Ok(Val::Str(a + &b.to_string())),
             (Val::Vec(mut a), Val::Vec(b)) => {
                 a.extend(b);
                 Ok(Val::Vec(a))
             }
-            _ => Err("Only numbers, strings and vectors can be added"),
+            (a, b) => a.arithmetic(
+                b,
+                i64::checked_add,
+                |a, b| a + b,
+                "Only numbers, strings and vectors can be added",
+            ),
         }
     }
     pub fn sub(self, other: Self) -> Result<Self, Error> {
-        match (self, other) {
-            (Val::Number(a), Val::Number(b)) => Ok(Val::Number(a - b)),
-            _ => Err("Only numbers can be subtracted"),
-        }
+        self.arithmetic(
+            other,
+            i64::checked_sub,
+            |a, b| a - b,
+            "Only numbers can be subtracted",
+        )
     }
 
     pub fn mul(self, other: Self) -> Result<Self, Error> {
-        match (self, other) {
-            (Val::Number(a), Val::Number(b)) => Ok(Val::Number(a * b)),
-            _ => Err("Only numbers can be multiplied"),
-        }
+        self.arithmetic(
+            other,
+            i64::checked_mul,
+            |a, b| a * b,
+            "Only numbers can be multiplied",
+        )
     }
 
+    /// Division always gives a float, even between two ints
     pub fn div(self, other: Self) -> Result<Self, Error> {
-        match (self, other) {
-            (Val::Number(a), Val::Number(b)) => Ok(Val::Number(a / b)),
-            _ => Err("Only numbers can be divided"),
+        match (&self, &other) {
+            (Val::Int(_), Val::Int(0)) => {
+                Err(Error::new(ErrorKind::ZeroDivision, "Division by zero"))
+            }
+            _ => match (self.as_float(), other.as_float()) {
+                (Some(a), Some(b)) => Ok(Val::Number(a / b)),
+                _ => Err(Error::new(ErrorKind::Type, "Only numbers can be divided")),
+            },
         }
     }
 
     pub fn rem(self, other: Self) -> Result<Self, Error> {
         match (self, other) {
-            (Val::Number(a), Val::Number(b)) => Ok(Val::Number(a % b)),
-            _ => Err("Remainder operator can only be applied to numbers"),
+            (Val::Int(_), Val::Int(0)) => {
+                Err(Error::new(ErrorKind::ZeroDivision, "Division by zero"))
+            }
+            (a, b) => a.arithmetic(
+                b,
+                i64::checked_rem,
+                |a, b| a % b,
+                "Remainder operator can only be applied to numbers",
+            ),
         }
     }
 
     pub fn not(self) -> Result<Self, Error> {
         match self {
             Val::Bool(a) => Ok(Val::Bool(!a)),
-            _ => Err("Not operator can only be applied to booleans"),
+            _ => Err(Error::new(
+                ErrorKind::Type,
+                "Not operator can only be applied to booleans",
+            )),
         }
     }
     pub fn minus(self) -> Result<Self, Error> {
         match self {
+            Val::Int(a) => a
+                .checked_neg()
+                .map(Val::Int)
+                .ok_or_else(|| Error::new(ErrorKind::Overflow, "Integer overflow")),
             Val::Number(a) => Ok(Val::Number(-a)),
-            _ => Err("Unary minus can only be applied to numbers"),
+            _ => Err(Error::new(
+                ErrorKind::Type,
+                "Unary minus can only be applied to numbers",
+            )),
         }
     }
 
     pub fn index(&mut self, i: Val) -> Result<&mut Self, Error> {
         match self {
-            Val::Vec(v) => match i {
-                Val::Number(n) => {
-                    if n.fract() == 0.0 {
-                        if v.len() > n as usize {
-                            Ok(&mut v[n as usize])
-                        } else {
-                            Err("Vector access out of bounds")
-                        }
-                    } else {
-                        Err("Can't index with a floating point number")
-                    }
-                }
-                _ => Err("Index must be a number"),
+            Val::Vec(v) => {
+                let position = vector_position(&i, v.len())?;
+                Ok(&mut v[position])
+            }
+            // `record.name` is indexed as `record["name"]` when it is written or indexed
+            Val::Record(r) => match i {
+                Val::Str(name) => r.get_mut(&name).ok_or_else(|| no_field("record", &name)),
+                _ => Err(Error::new(
+                    ErrorKind::Type,
+                    "Record fields are named by strings",
+                )),
+            },
+            Val::Map(m) => match m.get_mut(&i)? {
+                Some(value) => Ok(value),
+                None => Err(Error::new(
+                    ErrorKind::Index,
+                    format!("Key {} not found in map", i),
+                )),
             },
-            _ => Err("Index operator can only be applied to vectors"),
+            Val::Module(_) => Err(module_member_write(&i)),
+            _ => Err(Error::new(
+                ErrorKind::Type,
+                "Index operator can only be applied to vectors",
+            )),
         }
     }
 
     pub fn write_to_vec(&mut self, i: Val, value: Val) -> Result<(), Error> {
         match self {
-            Val::Vec(v) => match i {
-                Val::Number(n) => {
-                    if n.fract() == 0.0 {
-                        if v.len() > n as usize {
-                            v[n as usize] = value;
-                            Ok(())
-                        } else {
-                            Err("Vector access out of bounds")
-                        }
-                    } else {
-                        Err("Can't index with a floating point number")
-                    }
-                }
-                _ => Err("Index must be a number"),
-            },
-            _ => Err("Index operator can only be applied to vectors"),
+            Val::Vec(v) => {
+                let position = vector_position(&i, v.len())?;
+                v[position] = value;
+                Ok(())
+            }
+            Val::Record(_) => {
+                *self.index(i)? = value;
+                Ok(())
+            }
+            Val::Map(m) => m.insert(i, value),
+            Val::Module(_) => Err(module_member_write(&i)),
+            _ => Err(Error::new(
+                ErrorKind::Type,
+                "Index operator can only be applied to vectors",
+            )),
         }
     }
 
     pub fn and(&self, other: Val) -> Result<Self, Error> {
         match (self, other) {
             (Val::Bool(a), Val::Bool(b)) => Ok(Val::Bool(*a && b)),
-            _ => Err("Logical and can only be applied to booleans"),
+            _ => Err(Error::new(
+                ErrorKind::Type,
+                "Logical and can only be applied to booleans",
+            )),
         }
     }
 
     pub fn or(&self, other: Val) -> Result<Self, Error> {
         match (self, other) {
             (Val::Bool(a), Val::Bool(b)) => Ok(Val::Bool(*a || b)),
-            _ => Err("Logical or can only be applied to booleans"),
+            _ => Err(Error::new(
+                ErrorKind::Type,
+                "Logical or can only be applied to booleans",
+            )),
         }
     }
-    pub fn push(self, element: Val) -> Result<Self, Error> {
+    /// Elements visited by a for loop: the items of a vector, the characters of a string,
+    /// the numbers of a range or the keys of a map
+    pub fn iterate(self) -> Result<Box<dyn Iterator<Item = Val>>, Error> {
         match self {
-            Val::Vec(mut v) => {
+            Val::Vec(v) => Ok(Box::new(v.into_iter())),
+            Val::Str(s) => Ok(Box::new(
+                s.chars()
+                    .map(|c| Val::Str(c.to_string()))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            )),
+            Val::Range(r) => Ok(r.iter()),
+            Val::Map(m) => Ok(Box::new(m.entries.into_iter().map(|(key, _)| key))),
+            _ => Err(Error::new(
+                ErrorKind::Type,
+                "Only vectors, strings, ranges and maps can be iterated",
+            )),
+        }
+    }
+
+    /// Copy of the elements of a vector or the characters of a string selected by `range`
+    pub fn slice(&self, range: Range) -> Result<Self, Error> {
+        match self {
+            Val::Vec(v) => Ok(Val::Vec(
+                range
+                    .indexes(v.len())?
+                    .into_iter()
+                    .map(|i| v[i].clone())
+                    .collect(),
+            )),
+            Val::Str(s) => {
+                let chars = s.chars().collect::<Vec<_>>();
+                Ok(Val::Str(
+                    range
+                        .indexes(chars.len())?
+                        .into_iter()
+                        .map(|i| chars[i])
+                        .collect(),
+                ))
+            }
+            _ => Err(Error::new(
+                ErrorKind::Type,
+                "Only vectors and strings can be sliced",
+            )),
+        }
+    }
+
+    pub fn contains(&self, element: &Val) -> Result<bool, Error> {
+        match (self, element) {
+            (Val::Vec(v), e) => Ok(v.contains(e)),
+            (Val::Str(s), Val::Str(e)) => Ok(s.contains(e.as_str())),
+            (Val::Range(r), n) => Ok(r.contains(n)),
+            (Val::Map(m), key) => Ok(matches!(m.get(key), Ok(Some(_)))),
+            _ => Err(Error::new(
+                ErrorKind::Type,
+                "in operator can only be applied to vectors, strings, ranges and maps",
+            )),
+        }
+    }
+
+    pub fn push(&mut self, element: Val) -> Result<(), Error> {
+        match self {
+            Val::Vec(v) => {
                 v.push(element);
-                Ok(Val::Vec(v))
+                Ok(())
             }
-            _ => Err("push() can only be used on vectors"),
+            _ => Err(Error::new(
+                ErrorKind::Type,
+                "push() can only be used on vectors",
+            )),
+        }
+    }
+    /// Name of the type of the value, used in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Val::Bool(_) => "bool",
+            Val::Int(_) => "int",
+            Val::Number(_) => "float",
+            Val::Str(_) => "string",
+            Val::Vec(_) => "vector",
+            Val::Function(_) => "function",
+            Val::Range(_) => "range",
+            Val::Error(_) => "error",
+            Val::Module(_) => "module",
+            Val::Record(_) => "record",
+            Val::Map(_) => "map",
+            Val::Variant(_) => "variant",
+            Val::Null => "null",
         }
     }
+
+    /// Value of `self.name`. Errors have a message, a kind and the value that was thrown,
+    /// modules have their top level bindings and the payload of a variant is named by its enum.
+    pub fn field(&self, name: &str) -> Result<Val, Error> {
+        match (self, name) {
+            (Val::Record(r), _) => r.get(name).cloned().ok_or_else(|| no_field("record", name)),
+            (Val::Variant(v), _) => v
+                .payload
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value.clone())
+                .ok_or_else(|| no_field(&v.name, name)),
+            (Val::Error(e), "message") => Ok(Val::Str(e.message().to_owned())),
+            (Val::Error(e), "kind") => Ok(Val::Str(e.kind().to_string())),
+            (Val::Error(e), "value") => Ok(e.value().cloned().unwrap_or(Val::Null)),
+            (Val::Module(m), _) => m.members.get(name).cloned().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::Name,
+                    format!("Module {} has no member {}", m.name, name),
+                )
+            }),
+            _ => Err(no_field(self.type_name(), name)),
+        }
+    }
+
     pub fn pop(&mut self) -> Result<Val, Error> {
         match self {
             Val::Vec(ref mut v) => match v.pop() {
                 Some(e) => Ok(e),
-                None => Err("The vector is empty"),
+                None => Err(Error::new(ErrorKind::Index, "The vector is empty")),
             },
-            _ => Err("pop() can only be used on vectors"),
+            _ => Err(Error::new(
+                ErrorKind::Type,
+                "pop() can only be used on vectors",
+            )),
         }
     }
 }
@@ -4,11 +4,20 @@ pub mod tokenizer;
 
 #[cfg(test)]
 mod tests {
-    use expr_eval::{evaluator::Environment, val::Val};
+    use expr_eval::{
+        error::{Error, ErrorKind},
+        evaluator::Environment,
+        val::Val,
+    };
 
-    use crate::{parser::parse, runtime, tokenizer::tokenize};
+    use crate::{
+        parser::{parse, parse_file},
+        runtime,
+        tokenizer::tokenize,
+    };
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn while_loop() {
         let mut env = Environment::new();
         let code = 
@@ -23,6 +32,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn vector_declaration() {
         let mut env = Environment::new();
         let code = r#"a = [1+2,3*4,true,[1,2,3],"hello"]"#;
@@ -41,6 +51,7 @@ mod tests {
         );
     }
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn vector_read() {
         let mut env = Environment::new();
         let code = 
@@ -52,6 +63,7 @@ mod tests {
         assert_eq!(env.get_ref("b"), Ok(&Val::Number(2.0)));
     }
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn vector_write() {
         let mut env = Environment::new();
         let code = 
@@ -74,6 +86,7 @@ mod tests {
         );
     }
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn vector_2d_read() {
         let mut env = Environment::new();
         let code = 
@@ -86,6 +99,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn vector_3d_read() {
         let mut env = Environment::new();
         let code = 
@@ -98,6 +112,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn vector_2d_write() {
         let mut env = Environment::new();
         let code = 
@@ -118,6 +133,7 @@ mod tests {
         );
     }
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn vector_copy() {
         let mut env = Environment::new();
         let code = 
@@ -142,6 +158,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn empty_vector() {
         let mut env = Environment::new();
         let code = "a=[]";
@@ -150,4 +167,988 @@ mod tests {
         runtime::execute(&ast, &mut env).unwrap();
         assert_eq!(env.get_ref("a"), Ok(&Val::Vec(vec![])));
     }
+
+    #[test]
+    fn recursive_function() {
+        let mut env = Environment::new();
+        let code = 
+        "fn fib(n) {
+            if n < 2 {
+                return n
+            }
+            return fib(n - 1) + fib(n - 2)
+        }
+        a = fib(10)";
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("a"), Ok(&Val::Int(55)));
+    }
+
+    #[test]
+    fn deep_recursion() {
+        // Runs on the small stack of a test thread
+        let mut env = Environment::new();
+        let code =
+        "fn sum(v, i) {
+            if i == v.len() {
+                return 0
+            }
+            return v[i] + sum(v, i + 1)
+        }
+        fn down(n) {
+            if n == 0 {
+                return 0
+            }
+            return down(n - 1)
+        }
+        v = []
+        for i in 0..500 {
+            v.push(i)
+        }
+        a = sum(v, 0)
+        b = down(9000)
+        down(20000)";
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        let result = runtime::execute(&ast, &mut env);
+        assert_eq!(env.get_ref("a"), Ok(&Val::Int(124750)));
+        assert_eq!(env.get_ref("b"), Ok(&Val::Int(0)));
+        assert_eq!(result, Err("Maximum recursion depth exceeded".into()));
+    }
+
+    #[test]
+    fn function_locals() {
+        let mut env = Environment::new();
+        let code = 
+        "offset = 10
+        fn shift(a, b) {
+            c = a + b + offset
+            return c
+        }
+        d = shift(1, 2)";
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("d"), Ok(&Val::Int(13)));
+        assert_eq!(env.get_ref("c"), Err(Error::new(ErrorKind::Name, "Undeclared variable")));
+    }
+
+    #[test]
+    fn return_outside_function() {
+        let instructions = tokenize("return 1");
+        assert_eq!(parse(&instructions), Err("return outside of a function".into()));
+    }
+
+    #[test]
+    fn closure_captures_at_creation() {
+        let mut env = Environment::new();
+        let code = 
+        "n = 3
+        fn make_adder(n) {
+            return |x| x + n
+        }
+        callbacks = [make_adder(10), |x| x * n]
+        n = 100
+        a = callbacks[0](1)
+        b = callbacks[1](2)
+        fn count(n) {
+            fn step() {
+                return 1
+            }
+            f = |n| n == 0 ? 0 : step() + f(n - 1)
+            return f(n)
+        }
+        c = count(5)";
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("a"), Ok(&Val::Int(11)));
+        assert_eq!(env.get_ref("b"), Ok(&Val::Int(6)));
+        assert_eq!(env.get_ref("c"), Ok(&Val::Int(5)));
+    }
+
+    #[test]
+    fn break_and_continue() {
+        let mut env = Environment::new();
+        let code = 
+        "a = 0
+        b = 0
+        while true {
+            a = a + 1
+            if a % 2 == 0 {
+                continue
+            }
+            if a > 9 {
+                break
+            }
+            b = b + a
+        }";
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("a"), Ok(&Val::Int(11)));
+        assert_eq!(env.get_ref("b"), Ok(&Val::Int(25)));
+    }
+
+    #[test]
+    fn break_outside_loop() {
+        let code = 
+        "if true {
+            break
+        }";
+        let instructions = tokenize(code);
+        assert_eq!(parse(&instructions), Err("break outside of a loop".into()));
+    }
+
+    #[test]
+    fn for_loop() {
+        let mut env = Environment::new();
+        let code = 
+        r#"a = 0
+        for x in [1, 2, 3] {
+            a = a + x
+        }
+        b = ""
+        for c in "abc" {
+            b = c + b
+        }
+        d = 0
+        for i in 1..a + 1 {
+            d = d + i
+        }"#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("a"), Ok(&Val::Int(6)));
+        assert_eq!(env.get_ref("b"), Ok(&Val::Str("cba".to_owned())));
+        assert_eq!(env.get_ref("d"), Ok(&Val::Int(21)));
+        assert_eq!(env.get_ref("i"), Err(Error::new(ErrorKind::Name, "Undeclared variable")));
+    }
+
+    #[test]
+    fn range_slices() {
+        let mut env = Environment::new();
+        let code = 
+        r#"v = [1, 2, 3, 4, 5]
+        a = v[1..3]
+        b = v[2..]
+        c = v[4..=0 step -2]
+        s = "hello"
+        d = s[..=1]"#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        let numbers = |v: &[i64]| Val::Vec(v.iter().map(|n| Val::Int(*n)).collect());
+        assert_eq!(env.get_ref("a"), Ok(&numbers(&[2, 3])));
+        assert_eq!(env.get_ref("b"), Ok(&numbers(&[3, 4, 5])));
+        assert_eq!(env.get_ref("c"), Ok(&numbers(&[5, 3, 1])));
+        assert_eq!(env.get_ref("d"), Ok(&Val::Str("he".to_owned())));
+    }
+
+    #[test]
+    fn else_if_chain() {
+        let mut env = Environment::new();
+        let code = 
+        r#"a = []
+        for n in 0..4 {
+            if n == 0 {
+                a = a + ["zero"]
+            } else if n == 1 {
+                a = a + ["one"]
+            } else if n == 2 {
+                a = a + ["two"]
+            } else {
+                a = a + ["many"]
+            }
+        }"#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        let expected = ["zero", "one", "two", "many"];
+        assert_eq!(
+            env.get_ref("a"),
+            Ok(&Val::Vec(expected.iter().map(|s| Val::Str(s.to_string())).collect()))
+        );
+    }
+
+    #[test]
+    fn dangling_else() {
+        let instructions = tokenize("a = 1\nelse {\n}");
+        assert_eq!(parse(&instructions), Err("else without a preceding if block".into()));
+        let instructions = tokenize("if true {\n} else print a");
+        assert_eq!(parse(&instructions), Err("Expected bracket or if after else".into()));
+    }
+
+    #[test]
+    fn statement_at_end_of_input() {
+        assert_eq!(parse(&tokenize("print")), Err("Expression to print not found".into()));
+        assert_eq!(
+            parse(&tokenize("while true")),
+            Err("Expected bracket after while expression".into())
+        );
+    }
+
+    #[test]
+    fn match_statement() {
+        let mut env = Environment::new();
+        let code = 
+        r#"fn describe(v) {
+            match v {
+                1 => {
+                    return "one"
+                }
+                "a" => { return "letter" }
+                [x, [y, _]] => {
+                    return x + y
+                }
+                n if n > 10 => {
+                    return "big"
+                }
+                _ => {
+                    return null
+                }
+            }
+        }
+        a = [describe(1), describe("a"), describe([1, [2, 3]]), describe(11), describe(5)]"#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(
+            env.get_ref("a"),
+            Ok(&Val::Vec(vec![
+                Val::Str("one".to_owned()),
+                Val::Str("letter".to_owned()),
+                Val::Int(3),
+                Val::Str("big".to_owned()),
+                Val::Null
+            ]))
+        );
+    }
+
+    #[test]
+    fn block_scoping() {
+        let mut env = Environment::new();
+        let code = 
+        "a = 1
+        b = 1
+        if true {
+            let a = 2
+            a = a + 10
+            b = a
+            c = 3
+        }
+        let d";
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("a"), Ok(&Val::Int(1)));
+        assert_eq!(env.get_ref("b"), Ok(&Val::Int(12)));
+        assert_eq!(env.get_ref("c"), Err(Error::new(ErrorKind::Name, "Undeclared variable")));
+        assert_eq!(env.get_ref("d"), Ok(&Val::Null));
+        let instructions = tokenize("let a[0] = 1");
+        assert_eq!(parse(&instructions), Err("Only variables can be declared with let".into()));
+    }
+
+    #[test]
+    fn declarations_are_block_scoped() {
+        let mut env = Environment::new();
+        let code = 
+        "fn outer(n) {
+            fn countdown(n) {
+                if n == 0 {
+                    return 0
+                }
+                return countdown(n - 1)
+            }
+            return countdown(n)
+        }
+        if true {
+            struct Point { x, y }
+            enum Shape { Circle(r), Empty }
+            p = Point(1, 2)
+            s = Circle(3)
+        }
+        a = outer(3)";
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("a"), Ok(&Val::Int(0)));
+        let undeclared = Error::new(ErrorKind::Name, "Undeclared variable");
+        for name in ["countdown", "Point", "Circle", "Empty"] {
+            assert_eq!(env.get_ref(name), Err(undeclared.clone()));
+        }
+        let ast = parse(&tokenize("b = Point(1, 2)")).unwrap();
+        let result = runtime::execute(&ast, &mut env);
+        assert_eq!(result, Err(Error::new(ErrorKind::Name, "Undeclared function")));
+    }
+
+    #[test]
+    fn constants() {
+        let instructions = tokenize("const A = 1\nif true {\n a = 2\n A = 3\n}");
+        assert_eq!(parse(&instructions), Err("Cannot assign to constant A".into()));
+        let instructions = tokenize("const V = [1]\nV[0] = 2");
+        assert_eq!(parse(&instructions), Err("Cannot assign to constant V".into()));
+        let instructions = tokenize("const A = 1\nlet A = 2");
+        assert_eq!(parse(&instructions), Err("Cannot redeclare constant A".into()));
+        let instructions = tokenize("const A = 1\nif true {\n let A = 2\n A = 3\n}");
+        assert!(parse(&instructions).is_ok());
+        let instructions = tokenize("const A = 1\nfn f() {\n A = 2\n}");
+        assert_eq!(parse(&instructions), Err("Cannot assign to constant A".into()));
+        let instructions = tokenize("const A = 1\nfn f(A) {\n A = 2\n}");
+        assert!(parse(&instructions).is_ok());
+        let mut env = Environment::new();
+        env.declare_constant("A".to_string(), Val::Int(1)).unwrap();
+        let ast = parse(&tokenize("let A = 2")).unwrap();
+        assert_eq!(runtime::execute(&ast, &mut env), Err("Cannot redeclare constant A".into()));
+        assert_eq!(env.get_ref("A"), Ok(&Val::Int(1)));
+        let mut env = Environment::new();
+        env.declare_constant("LIMITS".to_string(), Val::Vec(vec![Val::Int(10), Val::Int(20)])).unwrap();
+        let code = 
+        "fn reset() {
+            LIMITS[0] = 0
+        }
+        a = LIMITS[1]
+        reset()";
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err("Cannot assign to constant LIMITS".into())
+        );
+        assert_eq!(env.get_ref("a"), Ok(&Val::Int(20)));
+    }
+
+    #[test]
+    fn compound_assignment() {
+        let mut env = Environment::new();
+        let code = 
+        "a = 1
+        a += 2
+        a *= 10
+        a -= 5
+        a /= 5
+        a %= 4
+        m = [[1, 2], [3, 4]]
+        for i in 0..2 {
+            m[i][1] += 10
+        }
+        m[0] += [5]
+        s = \"s\"
+        s += 1";
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("a"), Ok(&Val::Int(1)));
+        assert_eq!(
+            env.get_ref("m"),
+            Ok(&Val::Vec(vec![
+                Val::Vec(vec![Val::Int(1), Val::Int(12), Val::Int(5)]),
+                Val::Vec(vec![Val::Int(3), Val::Int(14)])
+            ]))
+        );
+        assert_eq!(env.get_ref("s"), Ok(&Val::Str("s1".to_owned())));
+        // A failed operation leaves the target as it was
+        let instructions = tokenize("m[1] += 1");
+        let ast = parse(&instructions).unwrap();
+        assert!(runtime::execute(&ast, &mut env).is_err());
+        assert_eq!(
+            env.get_ref("m").map(|m| m.to_string()),
+            Ok("[[1, 12, 5], [3, 14]]".to_owned())
+        );
+    }
+
+    #[test]
+    fn destructuring_assignment() {
+        let mut env = Environment::new();
+        let code = 
+        "a, b = 1, 2
+        a, b = b, a
+        [c, [d, _]] = [3, [4, 5]]
+        v = [6, 7, 8]
+        [e, f] = v";
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err("Arity mismatch: cannot assign 3 values to 2 targets".into())
+        );
+        assert_eq!(env.get_ref("a"), Ok(&Val::Int(2)));
+        assert_eq!(env.get_ref("b"), Ok(&Val::Int(1)));
+        assert_eq!(env.get_ref("c"), Ok(&Val::Int(3)));
+        assert_eq!(env.get_ref("d"), Ok(&Val::Int(4)));
+        assert_eq!(env.get_ref("e"), Err(Error::new(ErrorKind::Name, "Undeclared variable")));
+        let instructions = tokenize("const A = 1\nA, b = 2, 3");
+        assert_eq!(parse(&instructions), Err("Cannot assign to constant A".into()));
+    }
+
+    #[test]
+    fn try_catch_finally() {
+        let mut env = Environment::new();
+        let code = 
+        r#"log = []
+        fn check(n) {
+            if n < 0 {
+                throw "negative number"
+            }
+            return n
+        }
+        v = [1, 2]
+        for i in [1, 5, -1] {
+            try {
+                log += [check(i) + v[i]]
+            } catch (e) {
+                log += [e.kind, e.message]
+            } finally {
+                log += ["next"]
+            }
+        }
+        fn cleanup() {
+            try {
+                return 1
+            } finally {
+                log += ["cleanup"]
+            }
+        }
+        a = cleanup()"#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        let text = |s: &str| Val::Str(s.to_owned());
+        assert_eq!(
+            env.get_ref("log"),
+            Ok(&Val::Vec(vec![
+                Val::Int(3),
+                text("next"),
+                text("IndexError"),
+                text("Vector access out of bounds"),
+                text("next"),
+                text("UserError"),
+                text("negative number"),
+                text("next"),
+                text("cleanup")
+            ]))
+        );
+        assert_eq!(env.get_ref("a"), Ok(&Val::Int(1)));
+        let instructions = tokenize("try {\n throw \"oops\"\n} finally {\n}");
+        let ast = parse(&instructions).unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err(Error::thrown(Val::Str("oops".to_owned())))
+        );
+        let code = r#"code = 0
+        message = ""
+        try {
+            throw {code: 3}
+        } catch e {
+            code = e.value.code
+            message = e.message
+        }"#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("code"), Ok(&Val::Int(3)));
+        assert_eq!(env.get_ref("message"), Ok(&Val::Str("{code: 3}".to_owned())));
+    }
+
+    #[test]
+    fn assert_statement() {
+        let mut env = Environment::new();
+        let code = 
+        r#"a = [1, 2]
+        assert a[0] < a[1], "sorted"
+        assert a[0] + a[1] == 4, "sum of " + a"#;
+        let instructions = tokenize(code);
+        let ast = parse_file(&instructions, "checks.toy").unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err(Error::new(
+                ErrorKind::Assertion,
+                "checks.toy:3: assertion failed: a[0] + a[1] == 4 (left: 3, right: 4): sum of [1, 2]"
+            ))
+        );
+        let instructions = tokenize(r#"assert "1" == 1"#);
+        let ast = parse_file(&instructions, "checks.toy").unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err(Error::new(
+                ErrorKind::Assertion,
+                r#"checks.toy:1: assertion failed: "1" == 1 (left: "1", right: 1)"#
+            ))
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let mut env = Environment::new();
+        let code = r#"// if a { b }
+        a = 1 // a = 2
+        /* outer /* nested
+        */ a = 3 */
+        b = a /* inline */ + 1
+        url = "http://example.com /* kept */""#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("a"), Ok(&Val::Int(1)));
+        assert_eq!(env.get_ref("b"), Ok(&Val::Int(2)));
+        assert_eq!(
+            env.get_ref("url"),
+            Ok(&Val::Str("http://example.com /* kept */".to_owned()))
+        );
+        let instructions = tokenize("a = 1 /* never closed\n a = 2");
+        assert_eq!(parse(&instructions), Err("Unterminated block comment".into()));
+    }
+
+    #[test]
+    fn multiline_statements() {
+        let mut env = Environment::new();
+        let code = r#"table = [
+            [1, 2], // first row
+            [3, 4]
+        ]
+        a = 1; b = table[1][0]; c = "x;y"; d = 0; e = 1
+        if (a == 1 &&
+            b == 3) { d = a + b; e = 0 }"#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("b"), Ok(&Val::Int(3)));
+        assert_eq!(env.get_ref("c"), Ok(&Val::Str("x;y".to_owned())));
+        assert_eq!(env.get_ref("d"), Ok(&Val::Int(4)));
+        assert_eq!(env.get_ref("e"), Ok(&Val::Int(0)));
+    }
+
+    #[test]
+    fn import_modules() {
+        let dir = std::env::temp_dir().join("toy_import_modules");
+        let lib = dir.join("lib");
+        std::fs::create_dir_all(&lib).unwrap();
+        let write = |name: &str, code: &str| std::fs::write(dir.join(name), code).unwrap();
+        write("lib/shapes", "const sides = 4\nlet names = [\"square\"]\nfn perimeter(l) {\n return sides * l\n}");
+        write("calls", "offset = 1\nfn helper(x) {\n return x + offset\n}\nfn apply(f, x) {\n return f(x)\n}\nfn sq(x) {\n return x * x\n}\nfn make() {\n return |x| helper(x)\n}");
+        write("cycle_a", "import \"cycle_b\"");
+        write("cycle_b", "import \"cycle_a\"");
+        let script = dir.join("main").to_string_lossy().into_owned();
+
+        let mut env = Environment::new();
+        env.modules().borrow_mut().search_path = vec![lib];
+        let code = r#"import "shapes"
+        import "lib/shapes" as s
+        p = shapes.perimeter(3)
+        same = shapes.perimeter == s.perimeter"#;
+        let ast = parse_file(&tokenize(code), &script).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("p"), Ok(&Val::Int(12)));
+        assert_eq!(env.get_ref("same"), Ok(&Val::Bool(true)));
+
+        // Functions see the globals of the script or module where they were declared
+        let code = "import \"calls\" as m
+        scale = 3
+        fn triple(x) {
+            return x * scale
+        }
+        a = m.apply(triple, 2)
+        g = m.make()
+        b = g(4)
+        c = m.apply(|x| m.sq(x), 3)";
+        let ast = parse_file(&tokenize(code), &script).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("a"), Ok(&Val::Int(6)));
+        assert_eq!(env.get_ref("b"), Ok(&Val::Int(5)));
+        assert_eq!(env.get_ref("c"), Ok(&Val::Int(9)));
+
+        let ast = parse_file(&tokenize("x = shapes.area"), &script).unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err(Error::new(ErrorKind::Name, "Module shapes has no member area"))
+        );
+        let ast = parse_file(&tokenize("name = shapes.names[0]"), &script).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("name"), Ok(&Val::Str("square".into())));
+        for code in ["shapes.names = []", "shapes.names[0] = \"cube\"", "shapes.names += [\"cube\"]", "shapes.names.push(\"cube\")"] {
+            let ast = parse_file(&tokenize(code), &script).unwrap();
+            assert_eq!(
+                runtime::execute(&ast, &mut env),
+                Err(Error::new(ErrorKind::Type, "Cannot assign to module member names"))
+            );
+        }
+        let ast = parse_file(&tokenize("import \"cycle_a\""), &script).unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err(Error::new(
+                ErrorKind::Import,
+                "Circular import: cycle_a -> cycle_b -> cycle_a"
+            ))
+        );
+        let ast = parse_file(&tokenize("import \"missing\""), &script).unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err(Error::new(ErrorKind::Import, "Module missing not found"))
+        );
+    }
+
+    #[test]
+    fn records_and_structs() {
+        let mut env = Environment::new();
+        let code = r#"struct Point { x, y }
+        p = Point(1, 2)
+        p.x = 3
+        p.y += 1
+        a = {
+            name: "a",
+            b: [0, 1, {c: 0}]
+        }
+        a.b[2].c = 5
+        c = a.b[2].c
+        fn origin() {
+            return {coordinates: [Point(0, 1)]}
+        }
+        y = origin().coordinates[0].y
+        text = "" + p"#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("c"), Ok(&Val::Int(5)));
+        assert_eq!(env.get_ref("y"), Ok(&Val::Int(1)));
+        assert_eq!(
+            env.get_ref("text"),
+            Ok(&Val::Str("Point {x: 3, y: 3}".to_owned()))
+        );
+        let instructions = tokenize("a.d = 1");
+        let ast = parse(&instructions).unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err(Error::new(ErrorKind::Type, "record has no field d"))
+        );
+        let instructions = tokenize("origin().coordinates[0] = p");
+        assert_eq!(parse(&instructions), Err("Invalid assignment target".into()));
+        let instructions = tokenize("q = Point(1)");
+        let ast = parse(&instructions).unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err("Wrong number of arguments".into())
+        );
+    }
+
+    #[test]
+    fn maps() {
+        let mut env = Environment::new();
+        let code = r#"m = {"b": 1, "a": [0], 2: true}
+        m["b"] += 1
+        m["a"][0] = 5
+        m[false] = "inserted"
+        keys = []
+        for k in m {
+            keys = keys + [k]
+        }
+        text = "" + m
+        found = 2 in m && !("z" in m)
+        literal = []
+        for k in {"x": 1, "y": 2} {
+            literal = literal + [k in {"x": 1}]
+        }"#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(
+            env.get_ref("text"),
+            Ok(&Val::Str(r#"{"b": 2, "a": [5], 2: true, false: "inserted"}"#.to_owned()))
+        );
+        assert_eq!(
+            env.get_ref("keys"),
+            Ok(&Val::Vec(vec![
+                Val::Str("b".to_owned()),
+                Val::Str("a".to_owned()),
+                Val::Int(2),
+                Val::Bool(false)
+            ]))
+        );
+        assert_eq!(env.get_ref("found"), Ok(&Val::Bool(true)));
+        assert_eq!(env.get_ref("literal"), Ok(&Val::Vec(vec![Val::Bool(true), Val::Bool(false)])));
+        let instructions = tokenize("x = m[\"z\"]");
+        let ast = parse(&instructions).unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err(Error::new(ErrorKind::Index, "Key z not found in map"))
+        );
+        let instructions = tokenize("m[[1]] = 1");
+        let ast = parse(&instructions).unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err(Error::new(
+                ErrorKind::Type,
+                "Map keys must be strings, numbers or booleans"
+            ))
+        );
+    }
+
+    #[test]
+    fn enums() {
+        let mut env = Environment::new();
+        let code = r#"enum Shape {
+            Circle(r),
+            Rect(w, h), Empty
+        }
+        shapes = [Rect(2, 3), Empty, Circle(1)]
+        areas = []
+        for s in shapes {
+            match s {
+                Rect(w, h) => { areas = areas + [w * h] }
+                Empty => { areas = areas + [0] }
+                _ => { areas = areas + [s.r] }
+            }
+        }
+        width = 0
+        if let Rect(w, 3) = shapes[0] {
+            width = w
+        }
+        equal = Rect(2, 3) == shapes[0] && Rect(3, 2) != shapes[0]
+        text = "" + shapes"#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(
+            env.get_ref("areas"),
+            Ok(&Val::Vec(vec![
+                Val::Int(6),
+                Val::Int(0),
+                Val::Int(1)
+            ]))
+        );
+        assert_eq!(env.get_ref("width"), Ok(&Val::Int(2)));
+        assert_eq!(env.get_ref("equal"), Ok(&Val::Bool(true)));
+        assert_eq!(
+            env.get_ref("text"),
+            Ok(&Val::Str("[Rect(2, 3), Empty, Circle(1)]".to_owned()))
+        );
+        let duplicates = [
+            ("enum Shape { Empty }\nenum Stack { Empty }", "Cannot redeclare constant Empty"),
+            ("enum Shape { Circle(r) }\nenum Dot { Circle(x) }", "Cannot redeclare constant Circle"),
+            ("enum Shape { Empty }\nEmpty = 1", "Cannot assign to constant Empty"),
+            ("enum Shape { Circle(r) }\nlet Circle = 1", "Cannot redeclare constant Circle"),
+            ("enum Shape { Circle(r) }\nfn Circle(x) {\n return x\n}", "Cannot redeclare constant Circle"),
+        ];
+        for (code, error) in duplicates {
+            assert_eq!(parse(&tokenize(code)), Err(error.into()));
+        }
+    }
+
+    #[test]
+    fn method_calls() {
+        let mut env = Environment::new();
+        let code = r#"v = [3]
+        v.push(4)
+        last = v.pop()
+        grid = {rows: [[0], [1]]}
+        grid.rows[1].push(2)
+        words = " a,B ".trim().lower().split(",")
+        size = words.len() + (2).pow(3)"#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("v"), Ok(&Val::Vec(vec![Val::Int(3)])));
+        assert_eq!(env.get_ref("last"), Ok(&Val::Int(4)));
+        assert_eq!(
+            env.get_ref("grid").map(|g| g.to_string()),
+            Ok("{rows: [[0], [1, 2]]}".to_owned())
+        );
+        assert_eq!(
+            env.get_ref("words"),
+            Ok(&Val::Vec(vec![
+                Val::Str("a".to_owned()),
+                Val::Str("b".to_owned())
+            ]))
+        );
+        assert_eq!(env.get_ref("size"), Ok(&Val::Int(10)));
+        let instructions = tokenize("v.shuffle()");
+        let ast = parse(&instructions).unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err(Error::new(ErrorKind::Type, "vector has no method shuffle"))
+        );
+        let instructions = tokenize("const c = [1]\nn = c.len()\nc.push(2)");
+        let ast = parse(&instructions).unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err("Cannot assign to constant c".into())
+        );
+        assert_eq!(env.get_ref("n"), Ok(&Val::Int(1)));
+    }
+
+    #[test]
+    fn conditional_expressions() {
+        let mut env = Environment::new();
+        let code = r#"a = 3
+        v = [a > 2 ? "big" : "small", a < 2 ? 1 : 2]
+        picked = v[a == 3 ? 1 : 0]
+        lazy = false ? undefined_fn() : 1
+        sign = |x| x > 0 ? 1 : x < 0 ? -1 : 0
+        signs = [sign(-5), sign(0), sign(5)]"#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(
+            env.get_ref("v"),
+            Ok(&Val::Vec(vec![Val::Str("big".to_owned()), Val::Int(2)]))
+        );
+        assert_eq!(env.get_ref("picked"), Ok(&Val::Int(2)));
+        assert_eq!(env.get_ref("lazy"), Ok(&Val::Int(1)));
+        assert_eq!(
+            env.get_ref("signs"),
+            Ok(&Val::Vec(vec![
+                Val::Int(-1),
+                Val::Int(0),
+                Val::Int(1)
+            ]))
+        );
+        let instructions = tokenize("b = 1 ? 2 : 3");
+        let ast = parse(&instructions).unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err(Error::new(
+                ErrorKind::Type,
+                "Conditional expressions only work with booleans"
+            ))
+        );
+        for code in ["x = true ? : 1", "x = true ? 1 :", "x = ? 1 : 2"] {
+            assert_eq!(
+                parse(&tokenize(code)),
+                Err("Missing operand in conditional expression".into())
+            );
+        }
+    }
+
+    #[test]
+    fn string_interpolation() {
+        let mut env = Environment::new();
+        let code = r#"a = 2
+        m = {"key": [a, 3]}
+        text = "total: ${a + 3}, ${m["key"]} costs \$${a > 1 ? "many" : "one"}"
+        assert text.len() > 0, "empty ${text}"
+        lines = []
+        for n in 1..3 { lines.push("line ${n}") }
+        pairs = {}
+        pairs["a=${a}"] = "b=${a}"
+        lines.push("k=v")"#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(
+            env.get_ref("text"),
+            Ok(&Val::Str("total: 5, [2, 3] costs $many".to_owned()))
+        );
+        assert_eq!(
+            env.get_ref("lines"),
+            Ok(&Val::Vec(vec![
+                Val::Str("line 1".to_owned()),
+                Val::Str("line 2".to_owned()),
+                Val::Str("k=v".to_owned())
+            ]))
+        );
+        assert_eq!(
+            env.get_ref("pairs").map(|p| p.to_string()),
+            Ok(r#"{"a=2": "b=2"}"#.to_owned())
+        );
+        let instructions = tokenize(r#"b = "${}""#);
+        assert_eq!(parse(&instructions), Err("Empty interpolation".into()));
+    }
+
+    #[test]
+    fn escaped_quotes_and_tabs() {
+        let mut env = Environment::new();
+        let code = r#"name = "a \"b\""
+        json = "{\"name\": \"${name.replace("\"", "\\\"")}\"}"
+        tsv = ["x", 1].join("\t") + "\n""#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(
+            env.get_ref("json"),
+            Ok(&Val::Str(r#"{"name": "a \"b\""}"#.to_owned()))
+        );
+        assert_eq!(env.get_ref("tsv"), Ok(&Val::Str("x\t1\n".to_owned())));
+        let instructions = tokenize(r#"print "\u{110000}""#);
+        assert_eq!(
+            parse(&instructions),
+            Err(r#"Invalid escape sequence \u at character 2 of string "\u{110000}""#.into())
+        );
+    }
+
+    #[test]
+    fn multi_line_strings() {
+        let mut env = Environment::new();
+        let code = r##"table = "users"
+        query = """
+            SELECT "name"
+              FROM ${table} // not a comment
+
+            WHERE id = 1;
+            """
+        pattern = r#"^"\w+" {2}$"#
+        lines = query.split("\n").len()"##;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(
+            env.get_ref("query"),
+            Ok(&Val::Str(
+                "SELECT \"name\"\n  FROM users // not a comment\n\nWHERE id = 1;".to_owned()
+            ))
+        );
+        assert_eq!(
+            env.get_ref("pattern"),
+            Ok(&Val::Str(r#"^"\w+" {2}$"#.to_owned()))
+        );
+        assert_eq!(env.get_ref("lines"), Ok(&Val::Int(4)));
+    }
+
+    #[test]
+    fn integer_overflow() {
+        let mut env = Environment::new();
+        let code = r#"big = (2).pow(62)
+        kind = ""
+        try { big = big * 2 } catch e { kind = e.kind }
+        halves = []
+        for n in 0..3 { halves.push(n.div(2)) }
+        mean = (1 + 2) / 2.0
+        v = [1, 2, 3]
+        third = v[(2.9).to_int()]
+        large = []
+        for i in 9007199254740991..9007199254740994 { large.push(i) }
+        last = []
+        for i in 9223372036854775806.. { last.push(i) }"#;
+        let instructions = tokenize(code);
+        let ast = parse(&instructions).unwrap();
+        runtime::execute(&ast, &mut env).unwrap();
+        assert_eq!(env.get_ref("big"), Ok(&Val::Int(1 << 62)));
+        assert_eq!(env.get_ref("kind"), Ok(&Val::Str("OverflowError".to_owned())));
+        assert_eq!(
+            env.get_ref("halves"),
+            Ok(&Val::Vec(vec![Val::Int(0), Val::Int(0), Val::Int(1)]))
+        );
+        assert_eq!(env.get_ref("mean"), Ok(&Val::Number(1.5)));
+        assert_eq!(env.get_ref("third"), Ok(&Val::Int(3)));
+        assert_eq!(
+            env.get_ref("large"),
+            Ok(&Val::Vec(vec![
+                Val::Int(9007199254740991),
+                Val::Int(9007199254740992),
+                Val::Int(9007199254740993)
+            ]))
+        );
+        assert_eq!(
+            env.get_ref("last"),
+            Ok(&Val::Vec(vec![Val::Int(i64::MAX - 1), Val::Int(i64::MAX)]))
+        );
+        let instructions = tokenize("x = v[1.0]");
+        let ast = parse(&instructions).unwrap();
+        assert_eq!(
+            runtime::execute(&ast, &mut env),
+            Err(Error::new(
+                ErrorKind::Type,
+                "Can't index with a floating point number"
+            ))
+        );
+    }
 }
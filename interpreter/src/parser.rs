@@ -1,15 +1,77 @@
 use crate::tokenizer::*;
-use expr_eval::{parser::parse_expr, parser::ParseExprNode, tokenizer::tokenize_expr};
-type Error = &'static str;
+use expr_eval::{
+    error::Error,
+    parser::parse_expr,
+    parser::ParseExprNode,
+    tokenizer::{string_at, string_len, tokenize_expr, ExprToken},
+    val::Val,
+};
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum ParseNode {
     If(Box<ParseNode>, Vec<ParseNode>, Option<Vec<ParseNode>>), //If(Expression, If block, Else Block)
     While(Box<ParseNode>, Vec<ParseNode>),                      // While(Condition, Block)
-    Assignation(String, Box<ParseExprNode>),
-    VecWrite(String, Vec<ParseExprNode>, Box<ParseExprNode>), //Name of the vector, index, value to write
+    For(String, Box<ParseNode>, Vec<ParseNode>),                // For(Variable, Iterable, Block)
+    Assignation(Pattern, Box<ParseExprNode>), //Assignation(Target, Value), the target can be `a`, `a, b` or `[a, [b, _]]`
+    Let(String, Box<ParseExprNode>), // Let(Name, Value), declares a variable in the current block
+    Const(String, Box<ParseExprNode>), // Const(Name, Value), like Let but can't be reassigned
+    VecWrite(String, Vec<ParseExprNode>, Box<ParseExprNode>), //Name of the vector or record, index, value to write
+    CompoundAssignation(String, Vec<ParseExprNode>, Operator, Box<ParseExprNode>), //Name, index (empty for variables), operator, value
     Expression(ParseExprNode),
     Print(Box<ParseNode>),
+    Function(String, Vec<String>, Vec<ParseNode>), //Function(Name, Parameters, Body)
+    Return(Option<Box<ParseExprNode>>),
+    Break,
+    Continue,
+    Match(Box<ParseNode>, Vec<MatchArm>), // Match(Expression, Arms)
+    Try(Vec<ParseNode>, Option<Catch>, Option<Vec<ParseNode>>), // Try(Block, Catch, Finally block)
+    Throw(Box<ParseExprNode>),
+    Assert(
+        Box<ParseExprNode>,
+        Option<Box<ParseExprNode>>,
+        AssertLocation,
+    ), // Assert(Condition, Message, Location)
+    Import(String, String, String), // Import(Path, Module name, Path of the importing script)
+    Struct(String, Vec<String>),    // Struct(Name, Fields)
+    Enum(String, Vec<(String, Vec<String>)>), // Enum(Name, [(Variant, Payload fields)])
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Pattern {
+    Literal(Val),
+    Binding(String),
+    Vector(Vec<Pattern>),
+    Variant(String, Vec<Pattern>), // Variant(Name, Payload)
+    Wildcard,
+}
+
+/// Operator of a compound assignment such as `a += 1`
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+pub type MatchArm = (Pattern, Option<ParseExprNode>, Vec<ParseNode>); // (Pattern, Guard, Block)
+pub type Catch = (Option<String>, Vec<ParseNode>); // (Error variable, Block)
+
+/// Where an assert was written and the text of its condition, to explain why it failed
+#[derive(PartialEq, Debug, Clone)]
+pub struct AssertLocation {
+    pub file: String,
+    pub line: usize,
+    pub condition: String,
+}
+
+/// What surrounds the block being parsed, used to reject misplaced statements
+#[derive(Clone, Copy)]
+struct Context<'a> {
+    in_function: bool,
+    in_loop: bool,
+    file: &'a str, // Path of the script, reported by failed asserts. Imports are relative to it.
 }
 
 fn find_matching_bracket(tokens: &[Token]) -> Result<usize, Error> {
@@ -26,72 +88,645 @@ fn find_matching_bracket(tokens: &[Token]) -> Result<usize, Error> {
             _ => {}
         }
     }
-    Err("Unable to find matching bracket")
+    Err("Unable to find matching bracket".into())
 }
 
-fn parse_if(tokens: &[Token], i: &mut usize) -> Result<ParseNode, Error> {
+/// What a branch of an if checks: a boolean expression or, with `if let`, whether a value fits a pattern
+enum Condition {
+    Expression(Box<ParseNode>),
+    Pattern(Pattern, Box<ParseNode>), // Pattern(Pattern, Value)
+}
+
+impl Condition {
+    /// Node that runs `body` when the condition holds and `else_body` otherwise.
+    /// An `if let` becomes a match whose second arm is the else block.
+    fn into_node(self, body: Vec<ParseNode>, else_body: Option<Vec<ParseNode>>) -> ParseNode {
+        match self {
+            Condition::Expression(exp_ast) => ParseNode::If(exp_ast, body, else_body),
+            Condition::Pattern(pattern, value) => ParseNode::Match(
+                value,
+                vec![
+                    (pattern, None, body),
+                    (Pattern::Wildcard, None, else_body.unwrap_or_default()),
+                ],
+            ),
+        }
+    }
+}
+
+/// Parses the `pattern = value` of an `if let`
+fn parse_if_let(assignation: &str) -> Result<Condition, Error> {
+    let eq = mask_strings(assignation)
+        .find('=')
+        .ok_or("Expected pattern = value after if let")?;
+    let (pattern, value) = (&assignation[..eq], &assignation[eq + 1..]);
+    let pattern = expr_to_pattern(parse_expr(&tokenize_expr(pattern)?)?)?;
+    Ok(Condition::Pattern(pattern, parse_expression(value)?))
+}
+
+/// Parses `if condition { block }` or `if let pattern = value { block }`,
+/// leaving `i` on the closing bracket
+fn parse_if_branch(
+    tokens: &[Token],
+    i: &mut usize,
+    ctx: Context<'_>,
+) -> Result<(Condition, Vec<ParseNode>), Error> {
     *i += 1;
-    match tokens[*i] {
-        Token::Expression(exp) => match tokens[*i + 1] {
-            Token::OpenCBrackets => {
-                let exp_ast = parse_expression(exp)?;
-                let if_block_end = find_matching_bracket(&tokens[*i..])? + *i + 1;
-                let if_body = parse(&tokens[*i + 2..if_block_end - 1])?;
-                if if_block_end < tokens.len() && tokens[if_block_end] == Token::Else {
-                    let else_block_end =
-                        find_matching_bracket(&tokens[if_block_end..])? + if_block_end;
-                    let else_body = parse(&tokens[if_block_end + 2..else_block_end])?;
-                    *i = else_block_end;
-                    Ok(ParseNode::If(exp_ast, if_body, Some(else_body)))
-                } else {
-                    *i = if_block_end - 1;
-                    Ok(ParseNode::If(exp_ast, if_body, None))
-                }
+    let condition = match (tokens.get(*i), tokens.get(*i + 1)) {
+        (Some(Token::Let), Some(Token::Assignation(a))) => {
+            *i += 1;
+            parse_if_let(a)?
+        }
+        (Some(Token::Expression(exp)), _) => Condition::Expression(parse_expression(exp)?),
+        _ => return Err("Expected expression after if".into()),
+    };
+    match tokens.get(*i + 1) {
+        Some(Token::OpenCBrackets) => {
+            let block_end = find_matching_bracket(&tokens[*i..])? + *i;
+            let body = parse_block(&tokens[*i + 2..block_end], ctx)?;
+            *i = block_end;
+            Ok((condition, body))
+        }
+        _ => Err("Expected bracket after if expression".into()),
+    }
+}
+
+/// Parses an `if / else if / else` chain. Every `else if` becomes an `If`
+/// that is the only statement of the previous branch's else block.
+fn parse_if(tokens: &[Token], i: &mut usize, ctx: Context<'_>) -> Result<ParseNode, Error> {
+    let mut branches = vec![parse_if_branch(tokens, i, ctx)?];
+    let mut else_body = None;
+    while tokens.get(*i + 1) == Some(&Token::Else) {
+        *i += 2;
+        match tokens.get(*i) {
+            Some(Token::If) => branches.push(parse_if_branch(tokens, i, ctx)?),
+            Some(Token::OpenCBrackets) => {
+                let block_end = find_matching_bracket(&tokens[*i..])? + *i;
+                else_body = Some(parse_block(&tokens[*i + 1..block_end], ctx)?);
+                *i = block_end;
+                break;
             }
-            _ => Err("Expected bracket after if expression"),
+            _ => return Err("Expected bracket or if after else".into()),
+        }
+    }
+    let (condition, body) = branches.pop().ok_or("Expected if")?;
+    let mut chain = condition.into_node(body, else_body);
+    while let Some((condition, body)) = branches.pop() {
+        chain = condition.into_node(body, Some(vec![chain]));
+    }
+    Ok(chain)
+}
+
+fn expr_to_pattern(expr: ParseExprNode) -> Result<Pattern, Error> {
+    match expr {
+        ParseExprNode::VarName(name) if name == "_" => Ok(Pattern::Wildcard),
+        ParseExprNode::VarName(name) => Ok(Pattern::Binding(name)),
+        ParseExprNode::Number(v) | ParseExprNode::String(v) | ParseExprNode::Bool(v) => {
+            Ok(Pattern::Literal(v))
+        }
+        ParseExprNode::Null => Ok(Pattern::Literal(Val::Null)),
+        ParseExprNode::Neg(n) => match *n {
+            ParseExprNode::Number(v) => Ok(Pattern::Literal(v.minus()?)),
+            _ => Err("Invalid pattern".into()),
         },
-        _ => Err("Expected expression after if"),
+        ParseExprNode::Vector(v) => Ok(Pattern::Vector(
+            v.into_iter()
+                .map(expr_to_pattern)
+                .collect::<Result<Vec<_>, _>>()?,
+        )),
+        ParseExprNode::FnCall(name, payload) => Ok(Pattern::Variant(
+            name,
+            payload
+                .into_iter()
+                .map(expr_to_pattern)
+                .collect::<Result<Vec<_>, _>>()?,
+        )),
+        _ => Err("Invalid pattern".into()),
     }
 }
 
+/// Parses the text before `=>` in a match arm: a pattern optionally followed by `if guard`
+fn parse_pattern(arm: &str) -> Result<(Pattern, Option<ParseExprNode>), Error> {
+    let tokens = tokenize_expr(arm)?;
+    let guard_start = tokens
+        .iter()
+        .position(|t| *t == ExprToken::VarName("if".to_owned()));
+    let (pattern, guard) = match guard_start {
+        Some(p) => (&tokens[..p], Some(parse_expr(&tokens[p + 1..])?)),
+        None => (&tokens[..], None),
+    };
+    Ok((expr_to_pattern(parse_expr(pattern)?)?, guard))
+}
+
+fn parse_match(tokens: &[Token], i: &mut usize, ctx: Context<'_>) -> Result<ParseNode, Error> {
+    *i += 1;
+    let subject = match (tokens.get(*i), tokens.get(*i + 1)) {
+        (Some(Token::Expression(exp)), Some(Token::OpenCBrackets)) => parse_expression(exp)?,
+        _ => return Err("Expected expression and bracket after match".into()),
+    };
+    let match_end = find_matching_bracket(&tokens[*i..])? + *i;
+    let mut arms = vec![];
+    *i += 2;
+    while *i < match_end {
+        match (&tokens[*i], tokens.get(*i + 1)) {
+            (Token::MatchArm(arm), Some(Token::OpenCBrackets)) => {
+                let (pattern, guard) = parse_pattern(arm)?;
+                let block_end = find_matching_bracket(&tokens[*i..])? + *i;
+                let body = parse_block(&tokens[*i + 2..block_end], ctx)?;
+                arms.push((pattern, guard, body));
+                *i = block_end + 1;
+            }
+            _ => return Err("Expected pattern => { block } inside match".into()),
+        }
+    }
+    *i = match_end;
+    Ok(ParseNode::Match(subject, arms))
+}
+
 fn parse_print(tokens: &[Token], i: &mut usize) -> Result<ParseNode, Error> {
     *i += 1;
-    match tokens[*i] {
-        Token::Expression(e) => Ok(ParseNode::Print(parse_expression(e)?)),
-        _ => Err("Expression to print not found"),
+    match tokens.get(*i) {
+        Some(Token::Expression(e)) => Ok(ParseNode::Print(parse_expression(e)?)),
+        _ => Err("Expression to print not found".into()),
     }
 }
 
-fn parse_while(tokens: &[Token], i: &mut usize) -> Result<ParseNode, Error> {
+fn parse_while(tokens: &[Token], i: &mut usize, ctx: Context<'_>) -> Result<ParseNode, Error> {
     *i += 1;
-    match tokens[*i] {
-        Token::Expression(exp) => match tokens[*i + 1] {
-            Token::OpenCBrackets => {
+    match tokens.get(*i) {
+        Some(Token::Expression(exp)) => match tokens.get(*i + 1) {
+            Some(Token::OpenCBrackets) => {
                 let block_end = find_matching_bracket(&tokens[*i..])? + *i;
-                let body = parse(&tokens[*i + 2..block_end])?;
+                let loop_ctx = Context {
+                    in_loop: true,
+                    ..ctx
+                };
+                let body = parse_block(&tokens[*i + 2..block_end], loop_ctx)?;
                 let exp_ast = parse_expression(exp)?;
                 *i = block_end;
                 Ok(ParseNode::While(exp_ast, body))
             }
-            _ => Err("Expected bracket after while expression"),
+            _ => Err("Expected bracket after while expression".into()),
         },
-        _ => Err("Expected expression after while"),
+        _ => Err("Expected expression after while".into()),
+    }
+}
+
+fn parse_for_header(header: &str) -> Result<(String, Box<ParseNode>), Error> {
+    let (variable, rest) = header
+        .split_once(char::is_whitespace)
+        .ok_or("Expected in after for variable")?;
+    if !is_valid_name(variable) {
+        return Err("Invalid for variable name".into());
     }
+    let iterable = rest
+        .trim_start()
+        .strip_prefix("in")
+        .filter(|r| r.starts_with(|c: char| !c.is_alphanumeric() && c != '_'))
+        .ok_or("Expected in after for variable")?;
+    Ok((variable.to_owned(), parse_expression(iterable)?))
+}
+
+fn parse_for(tokens: &[Token], i: &mut usize, ctx: Context<'_>) -> Result<ParseNode, Error> {
+    *i += 1;
+    match tokens.get(*i) {
+        Some(Token::Expression(header)) => match tokens.get(*i + 1) {
+            Some(Token::OpenCBrackets) => {
+                let (variable, iterable) = parse_for_header(header)?;
+                let block_end = find_matching_bracket(&tokens[*i..])? + *i;
+                let loop_ctx = Context {
+                    in_loop: true,
+                    ..ctx
+                };
+                let body = parse_block(&tokens[*i + 2..block_end], loop_ctx)?;
+                *i = block_end;
+                Ok(ParseNode::For(variable, iterable, body))
+            }
+            _ => Err("Expected bracket after for expression".into()),
+        },
+        _ => Err("Expected expression after for".into()),
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn parse_signature(signature: &str) -> Result<(String, Vec<String>), Error> {
+    let (name, parameters) = signature
+        .strip_suffix(')')
+        .and_then(|s| s.split_once('('))
+        .ok_or("Expected parameter list after function name")?;
+    let name = name.trim();
+    if !is_valid_name(name) {
+        return Err("Invalid function name".into());
+    }
+    let parameters = parameters
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| match is_valid_name(p) {
+            true => Ok(p.to_owned()),
+            false => Err("Invalid parameter name".into()),
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok((name.to_owned(), parameters))
+}
+
+fn parse_fn(tokens: &[Token], i: &mut usize, ctx: Context<'_>) -> Result<ParseNode, Error> {
+    *i += 1;
+    match tokens.get(*i) {
+        Some(Token::Expression(signature)) => match tokens.get(*i + 1) {
+            Some(Token::OpenCBrackets) => {
+                let (name, parameters) = parse_signature(signature)?;
+                let block_end = find_matching_bracket(&tokens[*i..])? + *i;
+                let ctx = Context {
+                    in_function: true,
+                    in_loop: false,
+                    ..ctx
+                };
+                let body = parse_block(&tokens[*i + 2..block_end], ctx)?;
+                *i = block_end;
+                Ok(ParseNode::Function(name, parameters, body))
+            }
+            _ => Err("Expected bracket after function signature".into()),
+        },
+        _ => Err("Expected function signature after fn".into()),
+    }
+}
+
+fn parse_return(value: Option<&str>, ctx: Context<'_>) -> Result<ParseNode, Error> {
+    if !ctx.in_function {
+        return Err("return outside of a function".into());
+    }
+    let value = match value {
+        Some(v) => Some(Box::new(parse_expr(&tokenize_expr(v)?)?)),
+        None => None,
+    };
+    Ok(ParseNode::Return(value))
+}
+
+/// Wraps the tokens of a comma separated list, such as `a, b`, in square brackets
+/// so they are parsed as a vector. Other token lists are returned unchanged.
+fn comma_list_to_vector(mut tokens: Vec<ExprToken>) -> Vec<ExprToken> {
+    let mut depth = 0;
+    let mut in_parameters = false; // Commas between lambda parameters don't separate elements
+    let is_list = tokens.iter().any(|t| {
+        match t {
+            ExprToken::OpenParentheses
+            | ExprToken::OpenSBrackets
+            | ExprToken::OpenCBrackets
+            | ExprToken::FnCallStart(_) => depth += 1,
+            ExprToken::VecAccessStart(name) if name != "][" => depth += 1,
+            ExprToken::CloseParentheses | ExprToken::CloseSBrackets | ExprToken::CloseCBrackets => {
+                depth -= 1
+            }
+            ExprToken::Pipe => in_parameters = !in_parameters,
+            ExprToken::Comma => return depth == 0 && !in_parameters,
+            _ => {}
+        }
+        false
+    });
+    if is_list {
+        tokens.insert(0, ExprToken::OpenSBrackets);
+        tokens.push(ExprToken::CloseSBrackets);
+    }
+    tokens
+}
+
+/// Converts the left side of an assignment into the pattern its value is unpacked into
+fn expr_to_target(expr: ParseExprNode) -> Result<Pattern, Error> {
+    match expr {
+        ParseExprNode::VarName(name) if name == "_" => Ok(Pattern::Wildcard),
+        ParseExprNode::VarName(name) => Ok(Pattern::Binding(name)),
+        ParseExprNode::Vector(v) => Ok(Pattern::Vector(
+            v.into_iter()
+                .map(expr_to_target)
+                .collect::<Result<Vec<_>, _>>()?,
+        )),
+        _ => Err("Invalid assignment target".into()),
+    }
+}
+
+/// Variable and indexes of an element that can be written in place, such as `a[1]`
+/// or `a.b[2].c`. Fields are indexed by their name.
+fn expr_to_path(expr: ParseExprNode) -> Option<(String, Vec<ParseExprNode>)> {
+    match expr {
+        ParseExprNode::VarName(name) => Some((name, vec![])),
+        ParseExprNode::VecAccess(name, index) => Some((name, index)),
+        ParseExprNode::Field(value, field) => {
+            let (name, mut index) = expr_to_path(*value)?;
+            index.push(ParseExprNode::String(Val::Str(field)));
+            Some((name, index))
+        }
+        _ => None,
+    }
+}
+
+/// Parses the block that starts at `tokens[*i]`, leaving `i` on its closing bracket
+fn parse_bracketed_block(
+    tokens: &[Token],
+    i: &mut usize,
+    ctx: Context<'_>,
+    error: &'static str,
+) -> Result<Vec<ParseNode>, Error> {
+    if tokens.get(*i) != Some(&Token::OpenCBrackets) {
+        return Err(error.into());
+    }
+    let block_end = find_matching_bracket(&tokens[*i..])? + *i;
+    let block = parse_block(&tokens[*i + 1..block_end], ctx)?;
+    *i = block_end;
+    Ok(block)
+}
+
+/// Parses `struct Name { field, other_field }`
+fn parse_struct(tokens: &[Token], i: &mut usize) -> Result<ParseNode, Error> {
+    *i += 1;
+    let name = match tokens.get(*i) {
+        Some(Token::Expression(name)) if is_valid_name(name) => name.to_string(),
+        _ => return Err("Expected struct name after struct".into()),
+    };
+    *i += 1;
+    if tokens.get(*i) != Some(&Token::OpenCBrackets) {
+        return Err("Expected bracket after struct name".into());
+    }
+    let block_end = find_matching_bracket(&tokens[*i..])? + *i;
+    let mut fields: Vec<String> = vec![];
+    for token in &tokens[*i + 1..block_end] {
+        let names = match token {
+            Token::Expression(names) => names,
+            _ => return Err("Expected field names inside struct".into()),
+        };
+        for field in names.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            if !is_valid_name(field) {
+                return Err("Invalid field name".into());
+            }
+            if fields.iter().any(|f| f == field) {
+                return Err(format!("Duplicate field {} in struct {}", field, name).into());
+            }
+            fields.push(field.to_owned());
+        }
+    }
+    *i = block_end;
+    Ok(ParseNode::Struct(name, fields))
+}
+
+/// Parses a variant of an enum, such as `Empty` or `Rect(w, h)`
+fn parse_variant(variant: &str) -> Result<(String, Vec<String>), Error> {
+    let (name, fields) = match variant.strip_suffix(')') {
+        Some(v) => v.split_once('(').ok_or("Invalid enum variant")?,
+        None => (variant, ""),
+    };
+    let name = name.trim();
+    let fields = fields
+        .split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    if !is_valid_name(name) || !fields.iter().all(|f| is_valid_name(f)) {
+        return Err("Invalid enum variant".into());
+    }
+    Ok((name.to_owned(), fields))
+}
+
+/// Parses `enum Name { Variant, Other(field, other_field) }`
+fn parse_enum(tokens: &[Token], i: &mut usize) -> Result<ParseNode, Error> {
+    *i += 1;
+    let name = match tokens.get(*i) {
+        Some(Token::Expression(name)) if is_valid_name(name) => name.to_string(),
+        _ => return Err("Expected enum name after enum".into()),
+    };
+    *i += 1;
+    if tokens.get(*i) != Some(&Token::OpenCBrackets) {
+        return Err("Expected bracket after enum name".into());
+    }
+    let block_end = find_matching_bracket(&tokens[*i..])? + *i;
+    let mut variants: Vec<(String, Vec<String>)> = vec![];
+    for token in &tokens[*i + 1..block_end] {
+        let text = match token {
+            Token::Expression(text) => text,
+            _ => return Err("Expected variants inside enum".into()),
+        };
+        // Commas inside the parentheses of a variant separate its fields
+        let mut depth = 0;
+        let separators = text.match_indices(|c| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' => return depth == 0,
+                _ => {}
+            }
+            false
+        });
+        let mut start = 0;
+        for end in separators
+            .map(|(end, _)| end)
+            .chain(std::iter::once(text.len()))
+        {
+            let variant = text[start..end].trim();
+            start = end + 1;
+            if variant.is_empty() {
+                continue;
+            }
+            let variant = parse_variant(variant)?;
+            if variants.iter().any(|(v, _)| *v == variant.0) {
+                return Err(format!("Duplicate variant {} in enum {}", variant.0, name).into());
+            }
+            variants.push(variant);
+        }
+    }
+    *i = block_end;
+    Ok(ParseNode::Enum(name, variants))
+}
+
+/// Parses `try { } catch (e) { } finally { }`, where either catch or finally can be left out
+fn parse_try(tokens: &[Token], i: &mut usize, ctx: Context<'_>) -> Result<ParseNode, Error> {
+    *i += 1;
+    let body = parse_bracketed_block(tokens, i, ctx, "Expected bracket after try")?;
+    let mut catch = None;
+    if let Some(Token::Catch(variable)) = tokens.get(*i + 1) {
+        let variable = match variable.map(|v| v.trim_start_matches('(').trim_end_matches(')')) {
+            Some(v) if is_valid_name(v.trim()) => Some(v.trim().to_owned()),
+            Some(_) => return Err("Invalid catch variable name".into()),
+            None => None,
+        };
+        *i += 2;
+        let block = parse_bracketed_block(tokens, i, ctx, "Expected bracket after catch")?;
+        catch = Some((variable, block));
+    }
+    let mut finally = None;
+    if tokens.get(*i + 1) == Some(&Token::Finally) {
+        *i += 2;
+        finally = Some(parse_bracketed_block(
+            tokens,
+            i,
+            ctx,
+            "Expected bracket after finally",
+        )?);
+    }
+    if catch.is_none() && finally.is_none() {
+        return Err("Expected catch or finally after try block".into());
+    }
+    Ok(ParseNode::Try(body, catch, finally))
+}
+
+fn parse_throw(value: &str) -> Result<ParseNode, Error> {
+    if value.is_empty() {
+        return Err("Expected value after throw".into());
+    }
+    Ok(ParseNode::Throw(Box::new(parse_expr(&tokenize_expr(
+        value,
+    )?)?)))
+}
+
+/// Position of the comma that separates the condition of an assert from its message
+fn find_message_comma(assertion: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_parameters = false;
+    let mut i = 0;
+    while let Some(c) = assertion[i..].chars().next() {
+        match c {
+            _ if string_at(assertion, i) => {
+                i += string_len(&assertion[i..])?;
+                continue;
+            }
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '|' => in_parameters = !in_parameters,
+            ',' if depth == 0 && !in_parameters => return Some(i),
+            _ => {}
+        }
+        i += c.len_utf8();
+    }
+    None
+}
+
+/// Parses `assert condition` or `assert condition, message`
+fn parse_assert(assertion: &str, line: usize, ctx: Context<'_>) -> Result<ParseNode, Error> {
+    let (condition, message) = match find_message_comma(assertion) {
+        Some(comma) => (
+            assertion[..comma].trim(),
+            Some(assertion[comma + 1..].trim()),
+        ),
+        None => (assertion, None),
+    };
+    if condition.is_empty() {
+        return Err("Expected condition after assert".into());
+    }
+    let message = match message {
+        Some(m) => Some(Box::new(parse_expr(&tokenize_expr(m)?)?)),
+        None => None,
+    };
+    let location = AssertLocation {
+        file: ctx.file.to_owned(),
+        line,
+        condition: condition.to_owned(),
+    };
+    Ok(ParseNode::Assert(
+        Box::new(parse_expr(&tokenize_expr(condition)?)?),
+        message,
+        location,
+    ))
+}
+
+/// Parses `import "path"` or `import "path" as name`. Without a name, the module is
+/// named after its file.
+fn parse_import(import: &str, ctx: Context<'_>) -> Result<ParseNode, Error> {
+    let (path, rest) = import
+        .strip_prefix('"')
+        .and_then(|i| i.split_once('"'))
+        .ok_or("Expected path between quotes after import")?;
+    let name = match rest.trim() {
+        "" => std::path::Path::new(path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default(),
+        rest => strip_keyword(rest, "as")
+            .filter(|name| !name.is_empty())
+            .ok_or("Expected as after import path")?,
+    };
+    if !is_valid_name(name) {
+        return Err("Invalid module name, use import \"path\" as name".into());
+    }
+    Ok(ParseNode::Import(
+        path.to_owned(),
+        name.to_owned(),
+        ctx.file.to_owned(),
+    ))
 }
 
 fn parse_assignation(assignation_str: &str) -> Result<ParseNode, Error> {
-    let mut assignation = assignation_str.split('=').map(str::trim);
-    let err = "Error parsing asignation";
-    let dest = assignation.next().ok_or(err)?;
-    let src = Box::new(parse_expr(&tokenize_expr(assignation.next().ok_or(err)?)?)?);
-    if dest.contains('[') && dest.contains(']') {
-        let ast = parse_expr(&tokenize_expr(dest)?)?;
-        match ast {
-            ParseExprNode::VecAccess(name, index) => Ok(ParseNode::VecWrite(name, index, src)),
-            _ => Err("Failed to parse vector write"),
+    let eq = mask_strings(assignation_str)
+        .find('=')
+        .ok_or("Error parsing asignation")?;
+    let dest = assignation_str[..eq].trim();
+    let src_tokens = tokenize_expr(assignation_str[eq + 1..].trim())?;
+    let operator = match dest.chars().last() {
+        Some('+') => Some(Operator::Add),
+        Some('-') => Some(Operator::Sub),
+        Some('*') => Some(Operator::Mul),
+        Some('/') => Some(Operator::Div),
+        Some('%') => Some(Operator::Rem),
+        _ => None,
+    };
+    if let Some(operator) = operator {
+        let src = Box::new(parse_expr(&src_tokens)?);
+        let dest = dest[..dest.len() - 1].trim_end();
+        return match expr_to_path(parse_expr(&tokenize_expr(dest)?)?) {
+            Some((name, index)) => Ok(ParseNode::CompoundAssignation(name, index, operator, src)),
+            None => Err("Invalid target for compound assignment".into()),
+        };
+    }
+    match parse_expr(&comma_list_to_vector(tokenize_expr(dest)?))? {
+        dest @ (ParseExprNode::VecAccess(_, _) | ParseExprNode::Field(_, _)) => {
+            let (name, index) = expr_to_path(dest).ok_or("Invalid assignment target")?;
+            Ok(ParseNode::VecWrite(
+                name,
+                index,
+                Box::new(parse_expr(&src_tokens)?),
+            ))
         }
-    } else {
-        Ok(ParseNode::Assignation(dest.to_owned(), src))
+        ParseExprNode::VarName(name) => Ok(ParseNode::Assignation(
+            Pattern::Binding(name),
+            Box::new(parse_expr(&src_tokens)?),
+        )),
+        dest => Ok(ParseNode::Assignation(
+            expr_to_target(dest)?,
+            Box::new(parse_expr(&comma_list_to_vector(src_tokens))?),
+        )),
+    }
+}
+
+fn parse_let(tokens: &[Token], i: &mut usize) -> Result<ParseNode, Error> {
+    *i += 1;
+    match tokens.get(*i) {
+        Some(Token::Assignation(a)) => match parse_assignation(a)? {
+            ParseNode::Assignation(Pattern::Binding(name), value) => {
+                Ok(ParseNode::Let(name, value))
+            }
+            _ => Err("Only variables can be declared with let".into()),
+        },
+        Some(Token::Expression(name)) if is_valid_name(name) => Ok(ParseNode::Let(
+            name.to_string(),
+            Box::new(ParseExprNode::Null),
+        )),
+        _ => Err("Expected variable name after let".into()),
+    }
+}
+
+fn parse_const(tokens: &[Token], i: &mut usize) -> Result<ParseNode, Error> {
+    *i += 1;
+    match tokens.get(*i) {
+        Some(Token::Assignation(a)) => match parse_assignation(a)? {
+            ParseNode::Assignation(Pattern::Binding(name), value) => {
+                Ok(ParseNode::Const(name, value))
+            }
+            _ => Err("Only variables can be declared with const".into()),
+        },
+        _ => Err("Expected name and value after const".into()),
     }
 }
 
@@ -102,21 +737,179 @@ fn parse_expression(expression: &str) -> Result<Box<ParseNode>, Error> {
     Ok(Box::new(ParseNode::Expression(expr_ast)))
 }
 
-pub fn parse(tokens: &[Token]) -> Result<Vec<ParseNode>, Error> {
+fn parse_block(tokens: &[Token], ctx: Context<'_>) -> Result<Vec<ParseNode>, Error> {
     let mut ast = vec![];
     let mut i: usize = 0;
     while i < tokens.len() {
         match tokens[i] {
-            Token::If => ast.push(parse_if(&tokens, &mut i)?),
-            Token::Else => return Err("Unmatched else"),
-            Token::While => ast.push(parse_while(&tokens, &mut i)?),
+            Token::If => ast.push(parse_if(tokens, &mut i, ctx)?),
+            Token::Else => return Err("else without a preceding if block".into()),
+            Token::While => ast.push(parse_while(tokens, &mut i, ctx)?),
+            Token::For => ast.push(parse_for(tokens, &mut i, ctx)?),
+            Token::Match => ast.push(parse_match(tokens, &mut i, ctx)?),
+            Token::MatchArm(_) => return Err("Match arm outside of a match".into()),
             Token::Assignation(a) => ast.push(parse_assignation(a)?),
-            Token::OpenCBrackets => return Err("Unmatched {"),
-            Token::CloseCBrackets => return Err("Unmatched }"),
+            Token::Let => ast.push(parse_let(tokens, &mut i)?),
+            Token::Const => ast.push(parse_const(tokens, &mut i)?),
+            Token::OpenCBrackets => return Err("Unmatched {".into()),
+            Token::CloseCBrackets => return Err("Unmatched }".into()),
             Token::Expression(exp) => ast.push(*parse_expression(exp)?),
-            Token::Print => ast.push(parse_print(&tokens, &mut i)?),
+            Token::Print => ast.push(parse_print(tokens, &mut i)?),
+            Token::Fn => ast.push(parse_fn(tokens, &mut i, ctx)?),
+            Token::Return(value) => ast.push(parse_return(value, ctx)?),
+            Token::Break if ctx.in_loop => ast.push(ParseNode::Break),
+            Token::Continue if ctx.in_loop => ast.push(ParseNode::Continue),
+            Token::Try => ast.push(parse_try(tokens, &mut i, ctx)?),
+            Token::Catch(_) => return Err("catch without a preceding try block".into()),
+            Token::Finally => return Err("finally without a preceding try block".into()),
+            Token::Throw(value) => ast.push(parse_throw(value)?),
+            Token::Assert(assertion, line) => ast.push(parse_assert(assertion, line, ctx)?),
+            Token::Import(module) => ast.push(parse_import(module, ctx)?),
+            Token::Struct => ast.push(parse_struct(tokens, &mut i)?),
+            Token::Enum => ast.push(parse_enum(tokens, &mut i)?),
+            Token::Break => return Err("break outside of a loop".into()),
+            Token::Continue => return Err("continue outside of a loop".into()),
         }
         i += 1;
     }
     Ok(ast)
 }
+
+fn pattern_bindings(pattern: &Pattern, bindings: &mut Vec<(String, bool)>) {
+    match pattern {
+        Pattern::Binding(name) => bindings.push((name.clone(), false)),
+        Pattern::Vector(patterns) | Pattern::Variant(_, patterns) => {
+            patterns.iter().for_each(|p| pattern_bindings(p, bindings))
+        }
+        Pattern::Literal(_) | Pattern::Wildcard => {}
+    }
+}
+
+fn check_assignment(name: &str, bindings: &[(String, bool)]) -> Result<(), Error> {
+    match bindings.iter().rev().find(|(n, _)| n == name) {
+        Some((_, true)) => Err(format!("Cannot assign to constant {}", name).into()),
+        _ => Ok(()),
+    }
+}
+
+/// Rejects declaring again a constant of the same block
+fn check_declaration(name: &str, block_bindings: &[(String, bool)]) -> Result<(), Error> {
+    if block_bindings
+        .iter()
+        .any(|(n, constant)| n == name && *constant)
+    {
+        return Err(format!("Cannot redeclare constant {}", name).into());
+    }
+    Ok(())
+}
+
+/// Rejects the assignments to constants and the redeclarations of constants that can be
+/// detected before running the code. `bindings` holds the names declared with let or const
+/// that are visible from `block`, along with whether they are constants. `globals` holds
+/// the constants and enum variants declared at the top level of the script, which
+/// functions can see.
+fn check_constants(
+    block: &[ParseNode],
+    bindings: &mut Vec<(String, bool)>,
+    globals: &[(String, bool)],
+) -> Result<(), Error> {
+    let outer_bindings = bindings.len();
+    for node in block {
+        match node {
+            ParseNode::Let(name, _) | ParseNode::Import(_, name, _) => {
+                check_declaration(name, &bindings[outer_bindings..])?;
+                bindings.push((name.clone(), false))
+            }
+            ParseNode::Const(name, _) => {
+                check_declaration(name, &bindings[outer_bindings..])?;
+                bindings.push((name.clone(), true))
+            }
+            ParseNode::Assignation(target, _) => {
+                let mut targets = vec![];
+                pattern_bindings(target, &mut targets);
+                for (name, _) in &targets {
+                    check_assignment(name, bindings)?;
+                }
+            }
+            ParseNode::VecWrite(name, _, _) | ParseNode::CompoundAssignation(name, _, _, _) => {
+                check_assignment(name, bindings)?
+            }
+            ParseNode::If(_, if_block, else_block) => {
+                check_constants(if_block, bindings, globals)?;
+                if let Some(else_block) = else_block {
+                    check_constants(else_block, bindings, globals)?;
+                }
+            }
+            ParseNode::While(_, body) => check_constants(body, bindings, globals)?,
+            ParseNode::For(variable, _, body) => {
+                bindings.push((variable.clone(), false));
+                check_constants(body, bindings, globals)?;
+                bindings.pop();
+            }
+            ParseNode::Match(_, arms) => {
+                for (pattern, _, body) in arms {
+                    let arm_start = bindings.len();
+                    pattern_bindings(pattern, bindings);
+                    check_constants(body, bindings, globals)?;
+                    bindings.truncate(arm_start);
+                }
+            }
+            ParseNode::Try(body, catch, finally) => {
+                check_constants(body, bindings, globals)?;
+                if let Some((variable, block)) = catch {
+                    let catch_start = bindings.len();
+                    bindings.extend(variable.iter().map(|v| (v.clone(), false)));
+                    check_constants(block, bindings, globals)?;
+                    bindings.truncate(catch_start);
+                }
+                if let Some(block) = finally {
+                    check_constants(block, bindings, globals)?;
+                }
+            }
+            // Variants are constants, so two enums can't have variants with the same name
+            ParseNode::Enum(_, variants) => {
+                for (variant, _) in variants {
+                    check_declaration(variant, &bindings[outer_bindings..])?;
+                    bindings.push((variant.clone(), true));
+                }
+            }
+            // Functions see the top level bindings of the script when they are called,
+            // but not the variables of the blocks around them
+            ParseNode::Function(name, parameters, body) => {
+                check_declaration(name, globals)?;
+                let mut function_bindings = globals.to_vec();
+                function_bindings.extend(parameters.iter().map(|p| (p.clone(), false)));
+                check_constants(body, &mut function_bindings, globals)?;
+            }
+            _ => {}
+        }
+    }
+    bindings.truncate(outer_bindings);
+    Ok(())
+}
+
+pub fn parse(tokens: &[Token]) -> Result<Vec<ParseNode>, Error> {
+    parse_file(tokens, "<script>")
+}
+
+/// Like `parse`, for the tokens of the script at `file`
+pub fn parse_file(tokens: &[Token], file: &str) -> Result<Vec<ParseNode>, Error> {
+    let ctx = Context {
+        in_function: false,
+        in_loop: false,
+        file,
+    };
+    let ast = parse_block(tokens, ctx)?;
+    let globals: Vec<_> = ast
+        .iter()
+        .flat_map(|node| match node {
+            ParseNode::Const(name, _) => vec![(name.clone(), true)],
+            ParseNode::Enum(_, variants) => {
+                variants.iter().map(|(v, _)| (v.clone(), true)).collect()
+            }
+            _ => vec![],
+        })
+        .collect();
+    check_constants(&ast, &mut vec![], &globals)?;
+    Ok(ast)
+}
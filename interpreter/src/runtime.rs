@@ -1,20 +1,124 @@
-use crate::parser::ParseNode;
+use crate::{
+    parser::{parse_file, AssertLocation, Catch, MatchArm, Operator, ParseNode, Pattern},
+    tokenizer::tokenize,
+};
 
-use expr_eval::{self, evaluator::Environment, parser::ParseExprNode, val::Val};
+use expr_eval::{
+    self,
+    error::{Error, ErrorKind},
+    evaluator::{Environment, Function, GlobalScope},
+    parser::ParseExprNode,
+    val::{Module, Record, Val, Variant},
+};
+use std::{
+    collections::BTreeMap,
+    fs, iter, mem,
+    path::{Path, PathBuf},
+    rc::{Rc, Weak},
+};
+
+/// How a block finished executing
+#[derive(PartialEq, Debug)]
+pub enum ControlFlow {
+    Normal,
+    Return(Val),
+    Break,
+    Continue,
+}
+
+/// Function declared with `fn`. Its body sees its parameters, the globals of the script
+/// or module where it was declared, and its own name, so functions declared inside a
+/// block can call themselves.
+#[derive(Debug)]
+struct UserFunction {
+    name: String,
+    parameters: Vec<String>,
+    body: Vec<ParseNode>,
+    globals: GlobalScope,
+    this: Weak<UserFunction>,
+}
+
+impl Function for UserFunction {
+    fn call(&self, env: &mut Environment, arguments: Vec<Val>) -> Result<Val, Error> {
+        if arguments.len() != self.parameters.len() {
+            return Err("Wrong number of arguments".into());
+        }
+        let mut frame: BTreeMap<_, _> = self.parameters.iter().cloned().zip(arguments).collect();
+        if let (false, Some(this)) = (frame.contains_key(&self.name), self.this.upgrade()) {
+            frame.insert(self.name.clone(), Val::Function(this));
+        }
+        match env.call_in_frame(self.globals, frame, |env| execute(&self.body, env))? {
+            ControlFlow::Return(value) => Ok(value),
+            _ => Ok(Val::Null),
+        }
+    }
+}
+
+/// Creates the instances of a struct, taking the value of every field in order
+#[derive(Debug)]
+struct StructConstructor {
+    name: String,
+    fields: Vec<String>,
+}
+
+impl Function for StructConstructor {
+    fn call(&self, _env: &mut Environment, arguments: Vec<Val>) -> Result<Val, Error> {
+        if arguments.len() != self.fields.len() {
+            return Err("Wrong number of arguments".into());
+        }
+        Ok(Val::Record(Record {
+            name: Some(self.name.clone()),
+            fields: self.fields.iter().cloned().zip(arguments).collect(),
+        }))
+    }
+}
+
+/// Creates the variant of an enum that carries a payload, such as `Rect(1, 2)`
+#[derive(Debug)]
+struct VariantConstructor {
+    enum_name: String,
+    name: String,
+    fields: Vec<String>,
+}
+
+impl Function for VariantConstructor {
+    fn call(&self, _env: &mut Environment, arguments: Vec<Val>) -> Result<Val, Error> {
+        if arguments.len() != self.fields.len() {
+            return Err("Wrong number of arguments".into());
+        }
+        Ok(Val::Variant(Variant {
+            enum_name: self.enum_name.clone(),
+            name: self.name.clone(),
+            payload: self.fields.iter().cloned().zip(arguments).collect(),
+        }))
+    }
+}
 
 fn execute_if(
     expr: &expr_eval::parser::ParseExprNode,
     if_block: &[ParseNode],
     else_block: &Option<Vec<ParseNode>>,
     env: &mut Environment,
-) -> Result<(), &'static str> {
-    match env.evaluate(&expr) {
-        Ok(Val::Bool(true)) => execute(if_block, env),
-        Ok(Val::Bool(false)) => match else_block {
-            Some(e) => execute(e, env),
-            None => Ok(()),
-        },
-        _ => Err("if statement only works with booleans"),
+) -> Result<ControlFlow, Error> {
+    let (mut expr, mut if_block, mut else_block) = (expr, if_block, else_block);
+    loop {
+        match env.evaluate(expr) {
+            Ok(Val::Bool(true)) => return execute_block(if_block, env),
+            Ok(Val::Bool(false)) => match else_block.as_deref() {
+                // else if chains are followed here instead of recursing once per branch
+                Some([ParseNode::If(next_expr, next_block, next_else)]) => {
+                    match next_expr.as_ref() {
+                        ParseNode::Expression(e) => expr = e,
+                        _ => return Err("Error parsing if expression".into()),
+                    }
+                    if_block = next_block;
+                    else_block = next_else;
+                }
+                Some(e) => return execute_block(e, env),
+                None => return Ok(ControlFlow::Normal),
+            },
+            _ => return Err("if statement only works with booleans".into()),
+        }
     }
 }
 
@@ -22,36 +126,223 @@ fn execute_while(
     expr: &expr_eval::parser::ParseExprNode,
     block: &[ParseNode],
     env: &mut Environment,
-) -> Result<(), &'static str> {
-    while env.evaluate(&expr)? == Val::Bool(true) {
-        execute(block, env)?
+) -> Result<ControlFlow, Error> {
+    while env.evaluate(expr)? == Val::Bool(true) {
+        match execute_block(block, env)? {
+            ControlFlow::Normal | ControlFlow::Continue => {}
+            ControlFlow::Break => break,
+            flow => return Ok(flow),
+        }
+    }
+    Ok(ControlFlow::Normal)
+}
+
+fn execute_for(
+    variable: &str,
+    iterable: &ParseNode,
+    block: &[ParseNode],
+    env: &mut Environment,
+) -> Result<ControlFlow, Error> {
+    let items = match iterable {
+        ParseNode::Expression(e) => env.evaluate(e)?.iterate()?,
+        _ => return Err("Error parsing for iterable".into()),
+    };
+    for item in items {
+        env.push_scope();
+        env.declare(variable.to_owned(), item)?;
+        let flow = execute(block, env);
+        env.pop_scope();
+        match flow? {
+            ControlFlow::Normal | ControlFlow::Continue => {}
+            ControlFlow::Break => break,
+            flow => return Ok(flow),
+        }
+    }
+    Ok(ControlFlow::Normal)
+}
+
+/// Checks `value` against `pattern`, collecting the variables it binds.
+/// A name bound to a variant without payload, such as `Empty`, matches that variant.
+fn match_pattern(
+    pattern: &Pattern,
+    value: &Val,
+    env: &mut Environment,
+    bindings: &mut Vec<(String, Val)>,
+) -> bool {
+    match (pattern, value) {
+        (Pattern::Wildcard, _) => true,
+        (Pattern::Binding(name), value) => match env.get_ref(name) {
+            Ok(unit @ Val::Variant(v)) if v.name == *name && v.payload.is_empty() => unit == value,
+            _ => {
+                bindings.push((name.clone(), value.clone()));
+                true
+            }
+        },
+        (Pattern::Literal(literal), value) => literal == value,
+        (Pattern::Vector(patterns), Val::Vec(values)) => {
+            patterns.len() == values.len()
+                && patterns
+                    .iter()
+                    .zip(values)
+                    .all(|(p, v)| match_pattern(p, v, env, bindings))
+        }
+        (Pattern::Variant(name, patterns), Val::Variant(v)) => {
+            v.name == *name
+                && patterns.len() == v.payload.len()
+                && patterns
+                    .iter()
+                    .zip(&v.payload)
+                    .all(|(p, (_, v))| match_pattern(p, v, env, bindings))
+        }
+        (Pattern::Vector(_), _) | (Pattern::Variant(_, _), _) => false,
+    }
+}
+
+/// Declares the constructors of the variants of an enum as constants of the current scope.
+/// Variants without payload are values.
+fn execute_enum(
+    name: &str,
+    variants: &[(String, Vec<String>)],
+    env: &mut Environment,
+) -> Result<(), Error> {
+    for (variant, fields) in variants {
+        if fields.is_empty() {
+            let value = Variant {
+                enum_name: name.to_owned(),
+                name: variant.clone(),
+                payload: vec![],
+            };
+            env.declare_constant(variant.clone(), Val::Variant(value))?;
+        } else {
+            let constructor = VariantConstructor {
+                enum_name: name.to_owned(),
+                name: variant.clone(),
+                fields: fields.clone(),
+            };
+            env.declare_constant(variant.clone(), Val::Function(Rc::new(constructor)))?;
+        }
     }
     Ok(())
 }
 
+fn execute_match(
+    expr: &ParseExprNode,
+    arms: &[MatchArm],
+    env: &mut Environment,
+) -> Result<ControlFlow, Error> {
+    let value = env.evaluate(expr)?;
+    for (pattern, guard, block) in arms {
+        let mut bindings = vec![];
+        if !match_pattern(pattern, &value, env, &mut bindings) {
+            continue;
+        }
+        // The bindings of an arm are only visible in its guard and its block
+        env.push_scope();
+        for (name, value) in bindings {
+            env.declare(name, value)?;
+        }
+        let result = execute_arm(guard, block, env);
+        env.pop_scope();
+        if let Some(flow) = result? {
+            return Ok(flow);
+        }
+    }
+    Ok(ControlFlow::Normal)
+}
+
+fn execute_arm(
+    guard: &Option<ParseExprNode>,
+    block: &[ParseNode],
+    env: &mut Environment,
+) -> Result<Option<ControlFlow>, Error> {
+    let guard_passed = match guard {
+        Some(g) => match env.evaluate(g)? {
+            Val::Bool(b) => b,
+            _ => return Err("Match guards only work with booleans".into()),
+        },
+        None => true,
+    };
+    match guard_passed {
+        true => Ok(Some(execute(block, env)?)),
+        false => Ok(None),
+    }
+}
+
+/// Like `match_pattern`, but a value that doesn't fit the target of an assignment is an error
+fn destructure(
+    target: &Pattern,
+    value: Val,
+    bindings: &mut Vec<(String, Val)>,
+) -> Result<(), Error> {
+    match (target, value) {
+        (Pattern::Wildcard, _) => Ok(()),
+        (Pattern::Binding(name), value) => {
+            bindings.push((name.clone(), value));
+            Ok(())
+        }
+        (Pattern::Vector(targets), Val::Vec(values)) if targets.len() == values.len() => targets
+            .iter()
+            .zip(values)
+            .try_for_each(|(t, v)| destructure(t, v, bindings)),
+        (Pattern::Vector(targets), Val::Vec(values)) => Err(format!(
+            "Arity mismatch: cannot assign {} values to {} targets",
+            values.len(),
+            targets.len()
+        )
+        .into()),
+        (Pattern::Vector(_), _) => Err("Only vectors can be destructured".into()),
+        (Pattern::Literal(_), _) | (Pattern::Variant(_, _), _) => {
+            Err("Invalid assignment target".into())
+        }
+    }
+}
+
 fn execute_assignation(
-    variable: &str,
+    target: &Pattern,
     value: &expr_eval::parser::ParseExprNode,
     env: &mut Environment,
-) -> Result<(), &'static str> {
-    let computed_value = env.evaluate(&value)?;
-    let varname = variable.to_owned();
-    env.insert(varname, computed_value);
+) -> Result<(), Error> {
+    if let Pattern::Binding(variable) = target {
+        let computed_value = env.evaluate_binding(variable, value)?;
+        return env.insert(variable.to_owned(), computed_value);
+    }
+    let computed_value = env.evaluate(value)?;
+    let mut bindings = vec![];
+    destructure(target, computed_value, &mut bindings)?;
+    for (variable, value) in bindings {
+        env.insert(variable, value)?;
+    }
     Ok(())
 }
 
-fn execute_print(expression: &ParseNode, env: &mut Environment) -> Result<(), &'static str> {
+fn execute_let(variable: &str, value: &ParseExprNode, env: &mut Environment) -> Result<(), Error> {
+    let computed_value = env.evaluate_binding(variable, value)?;
+    env.declare(variable.to_owned(), computed_value)?;
+    Ok(())
+}
+
+fn execute_const(
+    constant: &str,
+    value: &ParseExprNode,
+    env: &mut Environment,
+) -> Result<(), Error> {
+    let computed_value = env.evaluate_binding(constant, value)?;
+    env.declare_constant(constant.to_owned(), computed_value)?;
+    Ok(())
+}
+
+fn execute_print(expression: &ParseNode, env: &mut Environment) -> Result<(), Error> {
     match expression {
         ParseNode::Expression(expr) => {
             println!("{}", env.evaluate(expr)?);
             Ok(())
         }
-        _ => Err("Only expressions can be printed"),
+        _ => Err("Only expressions can be printed".into()),
     }
 }
 
-fn execute_expression(expr: &ParseExprNode, env: &mut Environment) -> Result<(), &'static str> {
-    env.evaluate(&expr)?;
+fn execute_expression(expr: &ParseExprNode, env: &mut Environment) -> Result<(), Error> {
+    env.evaluate(expr)?;
     Ok(())
 }
 
@@ -60,7 +351,7 @@ fn execute_vector_write(
     index: &[ParseExprNode],
     value: ParseExprNode,
     env: &mut Environment,
-) -> Result<(), &'static str> {
+) -> Result<(), Error> {
     let computed_value = env.evaluate(&value)?;
     let mut computed_indexes = index
         .iter()
@@ -75,30 +366,317 @@ fn execute_vector_write(
     Ok(())
 }
 
-pub fn execute(ast: &[ParseNode], mut env: &mut Environment) -> Result<(), &'static str> {
+/// Applies `operator` to the variable or vector element and `value`, updating it in place
+fn execute_compound_assignation(
+    name: &str,
+    index: &[ParseExprNode],
+    operator: Operator,
+    value: &ParseExprNode,
+    env: &mut Environment,
+) -> Result<(), Error> {
+    let computed_value = env.evaluate(value)?;
+    let computed_indexes = index
+        .iter()
+        .map(|n| env.evaluate(n))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut target = env.get_mut_ref(name)?;
+    for i in computed_indexes {
+        target = target.index(i)?
+    }
+    // Appending to a string or a vector can't fail, so it takes the target and extends it
+    // in place. Other operators work on a copy, which is left untouched if they fail.
+    let appends = matches!(
+        (operator, &*target, &computed_value),
+        (Operator::Add, Val::Str(_), _) | (Operator::Add, Val::Vec(_), Val::Vec(_))
+    );
+    let current = match appends {
+        true => mem::replace(target, Val::Null),
+        false => target.clone(),
+    };
+    *target = match operator {
+        Operator::Add => current.add(computed_value)?,
+        Operator::Sub => current.sub(computed_value)?,
+        Operator::Mul => current.mul(computed_value)?,
+        Operator::Div => current.div(computed_value)?,
+        Operator::Rem => current.rem(computed_value)?,
+    };
+    Ok(())
+}
+
+/// Runs `block`, then the catch block if it failed and finally the finally block.
+/// A finally block that returns, breaks or continues overrides the result of the others.
+fn execute_try(
+    block: &[ParseNode],
+    catch: &Option<Catch>,
+    finally: &Option<Vec<ParseNode>>,
+    env: &mut Environment,
+) -> Result<ControlFlow, Error> {
+    let mut result = execute_block(block, env);
+    if let (Err(error), Some((variable, catch_block))) = (&result, catch) {
+        env.push_scope();
+        if let Some(variable) = variable {
+            env.declare(variable.clone(), Val::Error(Box::new(error.clone())))?;
+        }
+        result = execute(catch_block, env);
+        env.pop_scope();
+    }
+    if let Some(finally_block) = finally {
+        match execute_block(finally_block, env)? {
+            ControlFlow::Normal => {}
+            flow => return Ok(flow),
+        }
+    }
+    result
+}
+
+/// Errors are thrown as they are, any other value is kept by a new error
+fn execute_throw(value: &ParseExprNode, env: &mut Environment) -> Result<ControlFlow, Error> {
+    match env.evaluate(value)? {
+        Val::Error(error) => Err(*error),
+        value => Err(Error::thrown(value)),
+    }
+}
+
+/// Evaluates the condition of an assert. The operands of a comparison are
+/// evaluated separately, so they can be shown when it fails.
+fn evaluate_assertion(
+    condition: &ParseExprNode,
+    env: &mut Environment,
+) -> Result<(Val, Option<(Val, Val)>), Error> {
+    let operands = match condition {
+        ParseExprNode::Eq(s)
+        | ParseExprNode::NotEq(s)
+        | ParseExprNode::Gt(s)
+        | ParseExprNode::Lt(s)
+        | ParseExprNode::Gtoe(s)
+        | ParseExprNode::Ltoe(s) => s,
+        _ => return Ok((env.evaluate(condition)?, None)),
+    };
+    let left = env.evaluate(&operands[0])?;
+    let right = env.evaluate(&operands[1])?;
+    let passed = match condition {
+        ParseExprNode::Eq(_) => left == right,
+        ParseExprNode::NotEq(_) => left != right,
+        ParseExprNode::Gt(_) => left > right,
+        ParseExprNode::Lt(_) => left < right,
+        ParseExprNode::Gtoe(_) => left >= right,
+        _ => left <= right,
+    };
+    Ok((Val::Bool(passed), Some((left, right))))
+}
+
+fn execute_assert(
+    condition: &ParseExprNode,
+    message: &Option<Box<ParseExprNode>>,
+    location: &AssertLocation,
+    env: &mut Environment,
+) -> Result<(), Error> {
+    let (passed, operands) = evaluate_assertion(condition, env)?;
+    let place = format!("{}:{}", location.file, location.line);
+    match passed {
+        Val::Bool(true) => return Ok(()),
+        Val::Bool(false) => {}
+        _ => {
+            let report = format!("{}: assert only works with booleans", place);
+            return Err(Error::new(ErrorKind::Type, report));
+        }
+    }
+    let mut report = format!("{}: assertion failed: {}", place, location.condition);
+    if let Some((left, right)) = operands {
+        report += &format!(" (left: {}, right: {})", left.literal(), right.literal());
+    }
+    if let Some(message) = message {
+        report += &format!(": {}", env.evaluate(message)?);
+    }
+    Err(Error::new(ErrorKind::Assertion, report))
+}
+
+/// Declares a function in the current scope, like a variable declared with let
+fn execute_fn_declaration(
+    name: &str,
+    parameters: &[String],
+    body: &[ParseNode],
+    env: &mut Environment,
+) -> Result<(), Error> {
+    let function = Rc::new_cyclic(|this| UserFunction {
+        name: name.to_owned(),
+        parameters: parameters.to_vec(),
+        body: body.to_vec(),
+        globals: env.global_scope(),
+        this: this.clone(),
+    });
+    env.declare(name.to_owned(), Val::Function(function))
+}
+
+/// Finds the module at `path`, next to the importing script or else in the search path
+fn resolve_module(path: &str, importer: &str, env: &Environment) -> Result<PathBuf, Error> {
+    let importer_dir = Path::new(importer).parent().unwrap_or(Path::new(""));
+    let search_path = env.modules().borrow().search_path.clone();
+    iter::once(importer_dir.to_path_buf())
+        .chain(search_path)
+        .map(|dir| dir.join(path))
+        .find(|candidate| candidate.is_file())
+        .and_then(|found| found.canonicalize().ok())
+        .ok_or_else(|| Error::new(ErrorKind::Import, format!("Module {} not found", path)))
+}
+
+/// Executes the script at `path` with its own global scope and collects its globals
+fn load_module(path: &Path, env: &mut Environment) -> Result<Val, Error> {
+    let file = path.to_string_lossy();
+    let source = fs::read_to_string(path).map_err(|e| {
+        Error::new(
+            ErrorKind::Import,
+            format!("Unable to read module {}: {}", file, e),
+        )
+    })?;
+    let ast = parse_file(&tokenize(&source), &file)?;
+    let members = env.execute_module(|env| execute(&ast, env).map(drop))?;
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    Ok(Val::Module(Rc::new(Module {
+        name: name.into_owned(),
+        members,
+    })))
+}
+
+/// Binds the module at `path` to `name`. Modules are executed the first time they are
+/// imported, later imports reuse the same module.
+fn execute_import(
+    path: &str,
+    name: &str,
+    importer: &str,
+    env: &mut Environment,
+) -> Result<(), Error> {
+    let path = resolve_module(path, importer, env)?;
+    let loaded = env.modules().borrow().loaded.get(&path).cloned();
+    let module = match loaded {
+        Some(module) => module,
+        None => {
+            let mut modules = env.modules().borrow_mut();
+            if let Some(start) = modules.loading.iter().position(|p| *p == path) {
+                let cycle = modules.loading[start..]
+                    .iter()
+                    .chain(iter::once(&path))
+                    .map(|p| p.file_name().unwrap_or_default().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                let message = format!("Circular import: {}", cycle);
+                return Err(Error::new(ErrorKind::Import, message));
+            }
+            modules.loading.push(path.clone());
+            drop(modules);
+            let module = load_module(&path, env);
+            let mut modules = env.modules().borrow_mut();
+            modules.loading.pop();
+            let module = module?;
+            modules.loaded.insert(path, module.clone());
+            module
+        }
+    };
+    env.declare(name.to_owned(), module)?;
+    Ok(())
+}
+
+fn execute_return(
+    value: &Option<Box<ParseExprNode>>,
+    env: &mut Environment,
+) -> Result<ControlFlow, Error> {
+    match value {
+        Some(v) => Ok(ControlFlow::Return(env.evaluate(v)?)),
+        None => Ok(ControlFlow::Return(Val::Null)),
+    }
+}
+
+/// Executes `block` in a new scope, so its declarations disappear when it ends
+fn execute_block(block: &[ParseNode], env: &mut Environment) -> Result<ControlFlow, Error> {
+    env.push_scope();
+    let result = execute(block, env);
+    env.pop_scope();
+    result
+}
+
+pub fn execute(ast: &[ParseNode], env: &mut Environment) -> Result<ControlFlow, Error> {
     let mut i: usize = 0;
 
     while i < ast.len() {
-        match &ast[i] {
+        let flow = match &ast[i] {
             ParseNode::If(expr, if_block, else_block) => match expr.as_ref() {
                 ParseNode::Expression(e) => execute_if(e, if_block, else_block, env)?,
-                _ => return Err("Error parsing if expression"),
+                _ => return Err("Error parsing if expression".into()),
             },
             ParseNode::While(expr, block) => match expr.as_ref() {
                 ParseNode::Expression(e) => execute_while(e, block, env)?,
-                _ => return Err("Error parsing while expression"),
+                _ => return Err("Error parsing while expression".into()),
+            },
+            ParseNode::For(variable, iterable, block) => {
+                execute_for(variable, iterable, block, env)?
+            }
+            ParseNode::Match(expr, arms) => match expr.as_ref() {
+                ParseNode::Expression(e) => execute_match(e, arms, env)?,
+                _ => return Err("Error parsing match expression".into()),
             },
             ParseNode::Assignation(variable, value) => {
-                execute_assignation(variable, &value, &mut env)?
+                execute_assignation(variable, value, env)?;
+                ControlFlow::Normal
+            }
+            ParseNode::Let(variable, value) => {
+                execute_let(variable, value, env)?;
+                ControlFlow::Normal
+            }
+            ParseNode::Const(constant, value) => {
+                execute_const(constant, value, env)?;
+                ControlFlow::Normal
+            }
+            ParseNode::Expression(expr) => {
+                execute_expression(expr, env)?;
+                ControlFlow::Normal
+            }
+            ParseNode::Print(expression) => {
+                execute_print(expression, env)?;
+                ControlFlow::Normal
             }
-            ParseNode::Expression(expr) => execute_expression(expr, env)?,
-            ParseNode::Print(expression) => execute_print(expression, env)?,
             ParseNode::VecWrite(name, index, value) => {
-                execute_vector_write(name, index, *value.clone(), env)?
+                execute_vector_write(name, index, *value.clone(), env)?;
+                ControlFlow::Normal
+            }
+            ParseNode::CompoundAssignation(name, index, operator, value) => {
+                execute_compound_assignation(name, index, *operator, value, env)?;
+                ControlFlow::Normal
+            }
+            ParseNode::Function(name, parameters, body) => {
+                execute_fn_declaration(name, parameters, body, env)?;
+                ControlFlow::Normal
+            }
+            ParseNode::Return(value) => execute_return(value, env)?,
+            ParseNode::Try(block, catch, finally) => execute_try(block, catch, finally, env)?,
+            ParseNode::Throw(value) => execute_throw(value, env)?,
+            ParseNode::Assert(condition, message, location) => {
+                execute_assert(condition, message, location, env)?;
+                ControlFlow::Normal
             }
+            ParseNode::Struct(name, fields) => {
+                let constructor = StructConstructor {
+                    name: name.clone(),
+                    fields: fields.clone(),
+                };
+                env.declare(name.clone(), Val::Function(Rc::new(constructor)))?;
+                ControlFlow::Normal
+            }
+            ParseNode::Enum(name, variants) => {
+                execute_enum(name, variants, env)?;
+                ControlFlow::Normal
+            }
+            ParseNode::Import(path, name, importer) => {
+                execute_import(path, name, importer, env)?;
+                ControlFlow::Normal
+            }
+            ParseNode::Break => ControlFlow::Break,
+            ParseNode::Continue => ControlFlow::Continue,
+        };
+        if flow != ControlFlow::Normal {
+            return Ok(flow);
         }
         i += 1;
     }
 
-    Ok(())
+    Ok(ControlFlow::Normal)
 }
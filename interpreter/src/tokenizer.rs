@@ -1,52 +1,222 @@
+use expr_eval::tokenizer::{comment_len, string_at, string_len};
 use lazy_static::lazy_static;
-use regex::{Regex, RegexBuilder};
+use regex::Regex;
+use std::borrow::Cow;
 #[derive(PartialEq, Debug)]
 pub enum Token<'a> {
     If,
     Else,
     While,
+    For,
     Assignation(&'a str),
     OpenCBrackets,
     CloseCBrackets,
     Expression(&'a str),
     Print,
+    Fn,
+    Return(Option<&'a str>),
+    Break,
+    Continue,
+    Match,
+    MatchArm(&'a str),
+    Let,
+    Const,
+    Try,
+    Catch(Option<&'a str>),
+    Finally,
+    Throw(&'a str),
+    Assert(&'a str, usize), //Assert(Condition and message, Line)
+    Import(&'a str),
+    Struct,
+    Enum,
+}
+
+/// Copy of `text` with the contents of its string literals replaced by spaces, so that
+/// operators inside strings aren't mistaken for code. Positions in the copy are the same.
+pub(crate) fn mask_strings(text: &str) -> Cow<'_, str> {
+    if !text.contains('"') {
+        return Cow::Borrowed(text);
+    }
+    let mut masked = String::with_capacity(text.len());
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        if string_at(text, i) {
+            let len = string_len(&text[i..]).unwrap_or(text.len() - i);
+            masked.push('"');
+            masked.push_str(&" ".repeat(len - 1));
+            i += len;
+        } else {
+            masked.push(c);
+            i += c.len_utf8();
+        }
+    }
+    Cow::Owned(masked)
 }
 
 fn is_assignation(text: &str) -> bool {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"[^\{\}\n=]+\s*=\s*[^\{\}\n=]+").unwrap();
+        static ref RE: Regex = Regex::new(r"^[^\{\}\n=<>!]*[^\{\}\n=<>!\.]=[^\{\}\n=][^\{\}\n]*").unwrap();
+    }
+    RE.is_match(&mask_strings(text))
+}
+
+/// Line number of `text`, which must be a slice of `source_code`
+fn line_of(source_code: &str, text: &str) -> usize {
+    let offset = text.as_ptr() as usize - source_code.as_ptr() as usize;
+    source_code[..offset].matches('\n').count() + 1
+}
+
+/// Returns what follows `keyword` if `text` starts with it as a whole word
+pub(crate) fn strip_keyword<'a>(text: &'a str, keyword: &str) -> Option<&'a str> {
+    let rest = text.strip_prefix(keyword)?;
+    match rest.chars().next() {
+        Some(c) if c.is_alphanumeric() || c == '_' => None,
+        _ => Some(rest.trim()),
+    }
+}
+
+/// Keywords that are tokens on their own. The text after them is tokenized as a new statement.
+fn keyword_token<'a>(keyword: &str) -> Option<Token<'a>> {
+    match keyword {
+        "if" => Some(Token::If),
+        "else" => Some(Token::Else),
+        "while" => Some(Token::While),
+        "for" => Some(Token::For),
+        "match" => Some(Token::Match),
+        "print" => Some(Token::Print),
+        "fn" => Some(Token::Fn),
+        "let" => Some(Token::Let),
+        "const" => Some(Token::Const),
+        "break" => Some(Token::Break),
+        "continue" => Some(Token::Continue),
+        "try" => Some(Token::Try),
+        "finally" => Some(Token::Finally),
+        "struct" => Some(Token::Struct),
+        "enum" => Some(Token::Enum),
+        _ => None,
+    }
+}
+
+/// Tokenizes the text of a statement, which never contains curly brackets
+fn tokenize_statement<'a>(source_code: &'a str, mut text: &'a str, tokens: &mut Vec<Token<'a>>) {
+    loop {
+        text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        let word_end = text
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(text.len());
+        let rest = &text[word_end..];
+        if let Some(token) = keyword_token(&text[..word_end]) {
+            // Keywords followed by an expression must be separated from it by whitespace
+            let separated = rest.is_empty() || rest.starts_with(char::is_whitespace);
+            if separated
+                || matches!(
+                    token,
+                    Token::Break | Token::Continue | Token::Try | Token::Finally
+                )
+            {
+                tokens.push(token);
+                text = rest;
+                continue;
+            }
+        }
+        let token = if let Some(value) = strip_keyword(text, "return") {
+            Token::Return(Some(value).filter(|v| !v.is_empty()))
+        } else if let Some(variable) = strip_keyword(text, "catch") {
+            Token::Catch(Some(variable).filter(|v| !v.is_empty()))
+        } else if let Some(value) = strip_keyword(text, "throw") {
+            Token::Throw(value)
+        } else if let Some(assertion) = strip_keyword(text, "assert") {
+            Token::Assert(assertion, line_of(source_code, text))
+        } else if let Some(module) = strip_keyword(text, "import") {
+            Token::Import(module)
+        } else if let Some(arm) = text.strip_suffix("=>") {
+            Token::MatchArm(arm.trim())
+        } else if is_assignation(text) {
+            Token::Assignation(text)
+        } else {
+            Token::Expression(text)
+        };
+        tokens.push(token);
+        return;
+    }
+}
+
+/// Whether a curly bracket after `statement` opens a record instead of a block. It does
+/// where a value is expected, such as after an operator, a comma, `return` or `in`.
+fn opens_record(statement: &str) -> bool {
+    let statement = statement.trim_end();
+    let last_word = statement
+        .rsplit(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .next()
+        .unwrap_or_default();
+    let after_operator = statement.ends_with(|c| "=([,:?+-*/%!<>&|".contains(c));
+    (after_operator && !statement.ends_with("=>"))
+        || matches!(last_word, "return" | "print" | "throw" | "in")
+}
+
+/// Splits the source code into statements and curly brackets. Statements end at
+/// line breaks, semicolons and brackets, except inside strings and comments.
+/// Triple-quoted and raw strings can contain line breaks.
+/// Line breaks inside parentheses, square brackets or records don't end the statement.
+pub fn tokenize(source_code: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut statement_start = 0;
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < source_code.len() {
+        let rest = &source_code[i..];
+        let statement = &source_code[statement_start..i];
+        match rest.chars().next() {
+            // An unterminated string ends at the end of its line
+            Some(_) if string_at(source_code, i) => {
+                i += string_len(rest).unwrap_or_else(|| rest.find('\n').unwrap_or(rest.len()))
+            }
+            Some('(' | '[') => {
+                depth += 1;
+                i += 1;
+            }
+            Some('{') if depth > 0 || opens_record(statement) => {
+                depth += 1;
+                i += 1;
+            }
+            Some(')' | ']' | '}') if depth > 0 => {
+                depth -= 1;
+                i += 1;
+            }
+            Some('\n') if depth > 0 => i += 1,
+            Some(c @ ('{' | '}' | '\n' | ';')) => {
+                tokenize_statement(source_code, statement, &mut tokens);
+                match c {
+                    '{' => tokens.push(Token::OpenCBrackets),
+                    '}' => tokens.push(Token::CloseCBrackets),
+                    _ => {}
+                }
+                depth = 0;
+                i += 1;
+                statement_start = i;
+            }
+            Some('/') => match comment_len(rest) {
+                // A line comment ends the statement, a block comment is skipped by
+                // the expression tokenizer unless it is at the start of the statement
+                Ok(Some(len))
+                    if depth == 0 && (rest.starts_with("//") || statement.trim().is_empty()) =>
+                {
+                    tokenize_statement(source_code, statement, &mut tokens);
+                    i += len;
+                    statement_start = i;
+                }
+                Ok(Some(len)) => i += len,
+                Ok(None) => i += 1,
+                // The expression tokenizer reports the unterminated comment
+                Err(_) => i = source_code.len(),
+            },
+            Some(c) => i += c.len_utf8(),
+            None => break,
+        }
     }
-    RE.is_match(text)
-}
-
-pub fn tokenize(source_code: &str) -> Vec<Token> {
-    let patterns = [
-        r"\s*if\s+",                      //if
-        r"\s*else\s+",                    //else
-        r"\s*while\s+",                   //while
-        r"\s*print\s+",                   //print
-        r"\{|\}",                         //Curly brackets
-        r"[^\{\}\n=]+\s*=\s*[^\{\}\n=]+", //Assignation
-        r"[^\{\}\n]+",                    //Everything else
-    ]
-    .join("|");
-
-    RegexBuilder::new(&patterns)
-        //.unicode(false)
-        .build()
-        .unwrap()
-        .find_iter(source_code)
-        .map(|m| source_code[m.start()..m.end()].trim())
-        .filter(|&s| !s.is_empty())
-        .map(|cap| match cap.trim() {
-            "if" => Token::If,
-            "else" => Token::Else,
-            "while" => Token::While,
-            "{" => Token::OpenCBrackets,
-            "}" => Token::CloseCBrackets,
-            "print" => Token::Print,
-            a if is_assignation(a) => Token::Assignation(a),
-            expression => Token::Expression(expression),
-        })
-        .collect()
+    tokenize_statement(source_code, &source_code[statement_start..], &mut tokens);
+    tokens
 }
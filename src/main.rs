@@ -1,14 +1,18 @@
 use expr_eval::evaluator::Environment;
-use interpreter::{parser::parse, runtime, tokenizer::tokenize};
+use interpreter::{parser::parse_file, runtime, tokenizer::tokenize};
 use std::{env, fs};
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut env = Environment::new();
+    // Modules not found next to the importing script are searched in TOY_PATH
+    if let Some(search_path) = env::var_os("TOY_PATH") {
+        env.modules().borrow_mut().search_path = env::split_paths(&search_path).collect();
+    }
     let filename = env::args().nth(1).ok_or("Missing argument")?;
-    let contents = fs::read_to_string(filename)?;
+    let contents = fs::read_to_string(&filename)?;
     let instructions = tokenize(&contents);
     //dbg!(&instructions);
-    let ast = parse(&instructions)?;
+    let ast = parse_file(&instructions, &filename).map_err(|e| e.to_string())?;
     // dbg!(&ast);
-    runtime::execute(&ast, &mut env)?;
+    runtime::execute(&ast, &mut env).map_err(|e| e.to_string())?;
     Ok(())
 }